@@ -158,6 +158,8 @@ impl Assembler {
                 Instruction::LInstruction{ symbol } => {
                     self.symbol_table.insert(symbol.clone(), line_num);
                 },
+                // comments don't take up a ROM address
+                Instruction::Comment{ .. } => {},
                 _ => {
                     line_num = line_num + 1;
                 }
@@ -273,7 +275,7 @@ impl AssemblyWriter {
         }
     }
 
-    pub fn write(&self, compiled : Vec<u32>) {
+    pub fn write(&self, compiled : Vec<u32>) -> std::io::Result<()> {
         fn u32_to_string(i : u32) -> String {
             let mut out = Vec::<char>::new();
             for n in 0..16 {
@@ -286,9 +288,9 @@ impl AssemblyWriter {
             return out.into_iter().rev().collect();
         }
         
-        let mut f = File::create(&self.filename).expect("unable to create file");
+        let mut f = File::create(&self.filename)?;
         let hack : Vec<String> = compiled.iter().map(|i| u32_to_string(*i)).collect();
-        write!(f, "{}", hack.join("\r")).expect("Failed to write to file");    
+        write!(f, "{}", hack.join("\r"))
     }   
 }
 
@@ -9,6 +9,6 @@ fn main() {
     let mut assembler = Assembler::new();
     let assembly = assembler.assemble(&instructions);
     let assembly_writer = AssemblyWriter::new(filestem);
-    assembly_writer.write(assembly);
+    assembly_writer.write(assembly).expect("Failed to write to file");
 }
 
@@ -1,7 +1,7 @@
 use crate::compiler;
 use std::{iter::Peekable, collections::HashMap };
 use compiler::{KeyWord, Token, CompilationEngine};
-use vmtranslator::VMInstruction;
+use vmtranslator::{VMInstruction, Segment, PopSegment, ArithmeticOp};
 use std::fs::File;
 use std::io::prelude::*;
 
@@ -92,11 +92,11 @@ impl<T: Iterator<Item=Token>> VMCompiler<T> {
         if self.class_symbol_table.get(&name).is_none() {
             match kind.as_str() {
                 "static" => {
-                    self.class_symbol_table.insert(name, SymbolTableEntry { symbol_type: field_type, kind: "static".to_string(), index: self.static_symbol_counter });
+                    self.class_symbol_table.insert(name, SymbolTableEntry { symbol_type: field_type, kind: PopSegment::Static, index: self.static_symbol_counter });
                     self.static_symbol_counter+=1;                
                 },
                 "field" => {
-                    self.class_symbol_table.insert(name, SymbolTableEntry { symbol_type: field_type, kind: "this".to_string(), index: self.field_symbol_counter });
+                    self.class_symbol_table.insert(name, SymbolTableEntry { symbol_type: field_type, kind: PopSegment::This, index: self.field_symbol_counter });
                     self.field_symbol_counter+=1;                
                 },
                 err => panic!("Unexpected registration kind for class {:?}", err)
@@ -108,11 +108,11 @@ impl<T: Iterator<Item=Token>> VMCompiler<T> {
         if self.subroutine_symbol_table.get(&name).is_none() {
             match kind.as_str() {
                 "local" => {
-                    self.subroutine_symbol_table.insert(name, SymbolTableEntry { symbol_type: field_type, kind: "local".to_string(), index: self.local_symbol_counter});
+                    self.subroutine_symbol_table.insert(name, SymbolTableEntry { symbol_type: field_type, kind: PopSegment::Local, index: self.local_symbol_counter});
                     self.local_symbol_counter+=1;                
                 },
                 "argument" => {
-                    self.subroutine_symbol_table.insert(name, SymbolTableEntry { symbol_type: field_type, kind: "argument".to_string(), index: self.arg_symbol_counter });
+                    self.subroutine_symbol_table.insert(name, SymbolTableEntry { symbol_type: field_type, kind: PopSegment::Argument, index: self.arg_symbol_counter });
                     self.arg_symbol_counter+=1;                
                 },
                 err => panic!("Unexpected registration kind for subroutine {:?}", err)
@@ -134,7 +134,7 @@ impl<T: Iterator<Item=Token>> VMCompiler<T> {
 
 struct SymbolTableEntry {
     symbol_type : String,
-    kind: String,
+    kind: PopSegment,
     index : u8
 }
 
@@ -199,21 +199,21 @@ impl<T: Iterator<Item=Token>> CompilationEngine<VMInstruction> for VMCompiler<T>
             Token::KeyWord(KeyWord::Method) => {   
                 self.register_subroutine_symbol("argument".to_string(), self.class_name.as_ref().unwrap().clone(), "this".to_string());             
                 self.instruction_buffer.push(
-                    VMInstruction::CPush{ segment: "argument".to_string(), value: 0 }
+                    VMInstruction::CPush{ segment: Segment::Argument, value: 0 }
                 );
                 self.instruction_buffer.push(
-                    VMInstruction::CPop{  segment: "pointer".to_string(),  value: 0 }
+                    VMInstruction::CPop{  segment: PopSegment::Pointer,  value: 0 }
                 );
             },
             Token::KeyWord(KeyWord::Constructor) => {
                 self.instruction_buffer.push(
-                    VMInstruction::CPush{ segment: "constant".to_string(), value: self.field_symbol_counter as u32 }
+                    VMInstruction::CPush{ segment: Segment::Constant, value: self.field_symbol_counter as u32 }
                 );
                 self.instruction_buffer.push(
                     VMInstruction::CCall{ symbol: "Memory.alloc".to_string(), n_args: 1 }
                 );
                 self.instruction_buffer.push(
-                    VMInstruction::CPop{ segment: "pointer".to_string(), value: 0 }
+                    VMInstruction::CPop{ segment: PopSegment::Pointer, value: 0 }
                 );
             },
             err => panic!("Unexpected subroutine type {:?}", err)
@@ -224,7 +224,7 @@ impl<T: Iterator<Item=Token>> CompilationEngine<VMInstruction> for VMCompiler<T>
         self.compile_subroutine_body(); 
         // drop the value returned
         if !has_return_type {
-            self.instruction_buffer.push(VMInstruction::CPop{ segment: "temp".to_string(), value: 0 })
+            self.instruction_buffer.push(VMInstruction::CPop{ segment: PopSegment::Temp, value: 0 })
         }
         let class_name = self.class_name.as_ref().unwrap();
         self.instructions.push(
@@ -294,7 +294,7 @@ impl<T: Iterator<Item=Token>> CompilationEngine<VMInstruction> for VMCompiler<T>
         self.consume_keyword(KeyWord::Do);
         self.compile_expression();
         self.instruction_buffer.push (
-            VMInstruction::CPop { segment: "temp".to_string(), value: 0 }
+            VMInstruction::CPop { segment: PopSegment::Temp, value: 0 }
         );
         self.consume_symbol(';'); 
     }    
@@ -304,7 +304,7 @@ impl<T: Iterator<Item=Token>> CompilationEngine<VMInstruction> for VMCompiler<T>
         self.consume_symbol('(');
         self.compile_expression();
         self.instruction_buffer.push(
-            VMInstruction::CArithmetic { cmd: "not".to_string() }
+            VMInstruction::CArithmetic { cmd: ArithmeticOp::Not }
         ); 
         let if_counter = self.if_counter;
         self.if_counter+=1;                       
@@ -337,20 +337,20 @@ impl<T: Iterator<Item=Token>> CompilationEngine<VMInstruction> for VMCompiler<T>
         self.consume_keyword(KeyWord::Let);
         let id = self.process_identifier();
         let target = self.lookup_symbol(&id).unwrap();
-        let kind = target.kind.clone();
+        let kind = target.kind;
         let index = target.index.clone();
         if let Some(Token::Symbol('[')) = self.tokens.peek() {
-            self.instruction_buffer.push(VMInstruction::CPush { segment: kind, value: index as u32});
+            self.instruction_buffer.push(VMInstruction::CPush { segment: kind.into(), value: index as u32});
             self.consume_symbol('[');
             self.compile_expression();
-            self.instruction_buffer.push(VMInstruction::CArithmetic { cmd: "add".to_string() });
+            self.instruction_buffer.push(VMInstruction::CArithmetic { cmd: ArithmeticOp::Add });
             self.consume_symbol(']');
             self.consume_symbol('=');
             self.compile_expression();
-            self.instruction_buffer.push(VMInstruction::CPop { segment: "temp".to_string(), value: 0 });
-            self.instruction_buffer.push(VMInstruction::CPop { segment: "pointer".to_string(), value: 1 });
-            self.instruction_buffer.push(VMInstruction::CPush { segment: "temp".to_string(), value: 0 });
-            self.instruction_buffer.push(VMInstruction::CPop { segment: "that".to_string(), value: 0 });
+            self.instruction_buffer.push(VMInstruction::CPop { segment: PopSegment::Temp, value: 0 });
+            self.instruction_buffer.push(VMInstruction::CPop { segment: PopSegment::Pointer, value: 1 });
+            self.instruction_buffer.push(VMInstruction::CPush { segment: Segment::Temp, value: 0 });
+            self.instruction_buffer.push(VMInstruction::CPop { segment: PopSegment::That, value: 0 });
         } else {
             self.consume_symbol('=');
             self.compile_expression();
@@ -369,7 +369,7 @@ impl<T: Iterator<Item=Token>> CompilationEngine<VMInstruction> for VMCompiler<T>
         self.consume_symbol('(');
         self.compile_expression();
         self.instruction_buffer.push(
-            VMInstruction::CArithmetic { cmd: "not".to_string() }
+            VMInstruction::CArithmetic { cmd: ArithmeticOp::Not }
         );
         self.instruction_buffer.push(
             VMInstruction::CIf{ label: format!("end_while_{}", while_counter) }
@@ -392,7 +392,7 @@ impl<T: Iterator<Item=Token>> CompilationEngine<VMInstruction> for VMCompiler<T>
         match self.tokens.peek() {
             Some(Token::Symbol(c)) if *c == ';' => {
                 self.instruction_buffer.push( 
-                    VMInstruction::CPush{ segment: "constant".to_string(), value: 0}
+                    VMInstruction::CPush{ segment: Segment::Constant, value: 0}
                 );
             },
             _ => {
@@ -428,19 +428,19 @@ impl<T: Iterator<Item=Token>> CompilationEngine<VMInstruction> for VMCompiler<T>
             self.compile_term();        
             match op {
                 '<' => self.instruction_buffer.push(
-                    VMInstruction::CArithmetic{ cmd: "lt".to_string() }
+                    VMInstruction::CArithmetic{ cmd: ArithmeticOp::Lt }
                 ),
                 '>' => self.instruction_buffer.push(
-                    VMInstruction::CArithmetic{ cmd: "gt".to_string() }
+                    VMInstruction::CArithmetic{ cmd: ArithmeticOp::Gt }
                 ),
                 '=' => self.instruction_buffer.push(
-                    VMInstruction::CArithmetic{ cmd: "eq".to_string() }
+                    VMInstruction::CArithmetic{ cmd: ArithmeticOp::Eq }
                 ),
                 '+' => self.instruction_buffer.push(
-                    VMInstruction::CArithmetic{ cmd: "add".to_string() }
+                    VMInstruction::CArithmetic{ cmd: ArithmeticOp::Add }
                 ),
                 '-' => self.instruction_buffer.push(
-                    VMInstruction::CArithmetic{ cmd: "sub".to_string() }
+                    VMInstruction::CArithmetic{ cmd: ArithmeticOp::Sub }
                 ),
                 '*' => self.instruction_buffer.push(
                     VMInstruction::CCall{ symbol: "Math.multiply".to_string(), n_args: 2}
@@ -449,10 +449,10 @@ impl<T: Iterator<Item=Token>> CompilationEngine<VMInstruction> for VMCompiler<T>
                     VMInstruction::CCall{ symbol: "Math.divide".to_string(), n_args: 2}
                 ),
                 '&' => self.instruction_buffer.push(
-                    VMInstruction::CArithmetic{ cmd: "and".to_string() }
+                    VMInstruction::CArithmetic{ cmd: ArithmeticOp::And }
                 ),
                 '|' => self.instruction_buffer.push(
-                    VMInstruction::CArithmetic{ cmd: "or".to_string() }
+                    VMInstruction::CArithmetic{ cmd: ArithmeticOp::Or }
                 ),
                 _ => panic!("Unexpected binary operator {}", op)
             }
@@ -463,18 +463,18 @@ impl<T: Iterator<Item=Token>> CompilationEngine<VMInstruction> for VMCompiler<T>
         let token = self.tokens.next().unwrap();
         match token {
             Token::Int(i) => self.instruction_buffer.push(
-                VMInstruction::CPush{ segment: "constant".to_string(), value: i as u32 }
+                VMInstruction::CPush{ segment: Segment::Constant, value: i as u32 }
             ),
             Token::String(s) => { 
                 self.instruction_buffer.push(
-                    VMInstruction::CPush{ segment: "constant".to_string(), value: s.len() as u32 }
+                    VMInstruction::CPush{ segment: Segment::Constant, value: s.len() as u32 }
                 );
                 self.instruction_buffer.push(
                     VMInstruction::CCall { symbol: "String.new".to_string(), n_args: 1 }
                 );
                 for c in s.chars() {
                     self.instruction_buffer.push(
-                        VMInstruction::CPush{ segment: "constant".to_string(), value: c as u32 }
+                        VMInstruction::CPush{ segment: Segment::Constant, value: c as u32 }
                     );
                     self.instruction_buffer.push(
                         VMInstruction::CCall { symbol: "String.appendChar".to_string(), n_args: 2 }
@@ -483,20 +483,20 @@ impl<T: Iterator<Item=Token>> CompilationEngine<VMInstruction> for VMCompiler<T>
             },
             Token::KeyWord(KeyWord::True) => {
                 self.instruction_buffer.push(
-                    VMInstruction::CPush{ segment: "constant".to_string(), value: 1 }
+                    VMInstruction::CPush{ segment: Segment::Constant, value: 1 }
                 );
                 self.instruction_buffer.push(
-                    VMInstruction::CArithmetic{ cmd: "neg".to_string() }
+                    VMInstruction::CArithmetic{ cmd: ArithmeticOp::Neg }
                 );
             },
             Token::KeyWord(KeyWord::False) => self.instruction_buffer.push(
-                VMInstruction::CPush{ segment: "constant".to_string(), value: 0 }
+                VMInstruction::CPush{ segment: Segment::Constant, value: 0 }
             ),
             Token::KeyWord(KeyWord::Null) => self.instruction_buffer.push(
-                VMInstruction::CPush{ segment: "constant".to_string(), value: 0 }
+                VMInstruction::CPush{ segment: Segment::Constant, value: 0 }
             ),
             Token::KeyWord(KeyWord::This) => self.instruction_buffer.push(
-                VMInstruction::CPush{ segment: "pointer".to_string(), value: 0 }
+                VMInstruction::CPush{ segment: Segment::Pointer, value: 0 }
             ),
             Token::Symbol('(') => { 
                 self.compile_expression();
@@ -505,34 +505,34 @@ impl<T: Iterator<Item=Token>> CompilationEngine<VMInstruction> for VMCompiler<T>
             Token::Symbol(s) if s == '~' => {
                 self.compile_term();
                 self.instruction_buffer.push(
-                    VMInstruction::CArithmetic{ cmd: "not".to_string() }
+                    VMInstruction::CArithmetic{ cmd: ArithmeticOp::Not }
                 );
             },
             Token::Symbol(s) if s == '-' => {
                 self.compile_term();
                 self.instruction_buffer.push(
-                    VMInstruction::CArithmetic{ cmd: "neg".to_string() }
+                    VMInstruction::CArithmetic{ cmd: ArithmeticOp::Neg }
                 );
             },            
             Token::Identifier(id) => {
                 match self.tokens.peek().unwrap() {
                     Token::Symbol(s) if *s == '[' => {
                         let symbol_entry = self.lookup_symbol(&id).unwrap().clone();
-                        let segment = symbol_entry.kind.clone();
+                        let segment = symbol_entry.kind;
                         let value = symbol_entry.index;
                         self.instruction_buffer.push( 
-                            VMInstruction::CPush{ segment, value: value as u32 } 
+                            VMInstruction::CPush{ segment: segment.into(), value: value as u32 } 
                         );
                         self.consume_symbol('[');
                         self.compile_expression();
                         self.instruction_buffer.push( 
-                            VMInstruction::CArithmetic{ cmd: "add".to_string() }
+                            VMInstruction::CArithmetic{ cmd: ArithmeticOp::Add }
                         );
                         self.instruction_buffer.push( 
-                            VMInstruction::CPop{ segment : "pointer".to_string(), value: 1 } 
+                            VMInstruction::CPop{ segment: PopSegment::Pointer, value: 1 } 
                         );                        
                         self.instruction_buffer.push( 
-                            VMInstruction::CPush{ segment : "that".to_string(), value: 0 } 
+                            VMInstruction::CPush{ segment: Segment::That, value: 0 } 
                         );
                         self.consume_symbol(']');                        
                     },
@@ -541,7 +541,7 @@ impl<T: Iterator<Item=Token>> CompilationEngine<VMInstruction> for VMCompiler<T>
                         let mut class_name = id.clone();
                         let method_instruction = symbol_table_entry.map( |entry| {
                                 class_name = entry.symbol_type.clone();
-                                VMInstruction::CPush{ segment: entry.kind.clone(), value : entry.index as u32 }
+                                VMInstruction::CPush{ segment: entry.kind.into(), value : entry.index as u32 }
                             }
                         );
                         
@@ -569,7 +569,7 @@ impl<T: Iterator<Item=Token>> CompilationEngine<VMInstruction> for VMCompiler<T>
                         let class_name = self.class_name.as_ref().unwrap().clone();
                         // push pointer to current object
                         self.instruction_buffer.push( 
-                            VMInstruction::CPush{ segment : "pointer".to_string(), value: 0 }
+                            VMInstruction::CPush{ segment: Segment::Pointer, value: 0 }
                         );
                         // add the args
                         let n_args = self.compile_expression_list();
@@ -584,10 +584,10 @@ impl<T: Iterator<Item=Token>> CompilationEngine<VMInstruction> for VMCompiler<T>
                     },
                     _ => {
                         let symbol_entry = self.lookup_symbol(&id).unwrap().clone();
-                        let segment = symbol_entry.kind.clone();
+                        let segment = symbol_entry.kind;
                         let value = symbol_entry.index;
                         self.instruction_buffer.push( 
-                            VMInstruction::CPush{ segment, value: value as u32 }
+                            VMInstruction::CPush{ segment: segment.into(), value: value as u32 }
                         );
                     }
                 }
@@ -1,20 +1,178 @@
+pub mod optimizer;
+
 use std::fs::File;
 use std::io::prelude::*;
 use regex::Regex;
 use lazy_static::lazy_static;
 use assembler::Instruction;
 use std::fmt;
+use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use optimizer::Optimizer;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Segment {
+    Constant,
+    Local,
+    Argument,
+    This,
+    That,
+    Temp,
+    Static,
+    Pointer
+}
+
+impl fmt::Display for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match *self {
+            Self::Constant => "constant",
+            Self::Local => "local",
+            Self::Argument => "argument",
+            Self::This => "this",
+            Self::That => "that",
+            Self::Temp => "temp",
+            Self::Static => "static",
+            Self::Pointer => "pointer"
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Segment {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Segment, String> {
+        match s {
+            "constant" => Ok(Self::Constant),
+            "local" => Ok(Self::Local),
+            "argument" => Ok(Self::Argument),
+            "this" => Ok(Self::This),
+            "that" => Ok(Self::That),
+            "temp" => Ok(Self::Temp),
+            "static" => Ok(Self::Static),
+            "pointer" => Ok(Self::Pointer),
+            _ => Err(format!("Unknown segment {}", s))
+        }
+    }
+}
+
+// The segments that can be popped into, everything but constant
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PopSegment {
+    Local,
+    Argument,
+    This,
+    That,
+    Temp,
+    Static,
+    Pointer
+}
+
+impl From<PopSegment> for Segment {
+    fn from(segment : PopSegment) -> Segment {
+        match segment {
+            PopSegment::Local => Segment::Local,
+            PopSegment::Argument => Segment::Argument,
+            PopSegment::This => Segment::This,
+            PopSegment::That => Segment::That,
+            PopSegment::Temp => Segment::Temp,
+            PopSegment::Static => Segment::Static,
+            PopSegment::Pointer => Segment::Pointer
+        }
+    }
+}
+
+impl TryFrom<Segment> for PopSegment {
+    type Error = String;
+
+    fn try_from(segment : Segment) -> Result<PopSegment, String> {
+        match segment {
+            Segment::Constant => Err("Cannot pop into the constant segment".to_string()),
+            Segment::Local => Ok(PopSegment::Local),
+            Segment::Argument => Ok(PopSegment::Argument),
+            Segment::This => Ok(PopSegment::This),
+            Segment::That => Ok(PopSegment::That),
+            Segment::Temp => Ok(PopSegment::Temp),
+            Segment::Static => Ok(PopSegment::Static),
+            Segment::Pointer => Ok(PopSegment::Pointer)
+        }
+    }
+}
+
+impl fmt::Display for PopSegment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Segment::from(*self))
+    }
+}
+
+impl FromStr for PopSegment {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<PopSegment, String> {
+        s.parse::<Segment>()?.try_into()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithmeticOp {
+    Add,
+    Sub,
+    Neg,
+    Eq,
+    Gt,
+    Lt,
+    And,
+    Or,
+    Not
+}
+
+impl fmt::Display for ArithmeticOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match *self {
+            Self::Add => "add",
+            Self::Sub => "sub",
+            Self::Neg => "neg",
+            Self::Eq => "eq",
+            Self::Gt => "gt",
+            Self::Lt => "lt",
+            Self::And => "and",
+            Self::Or => "or",
+            Self::Not => "not"
+        };
+        write!(f, "{}", name)
+    }
+}
 
+impl FromStr for ArithmeticOp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ArithmeticOp, String> {
+        match s {
+            "add" => Ok(Self::Add),
+            "sub" => Ok(Self::Sub),
+            "neg" => Ok(Self::Neg),
+            "eq" => Ok(Self::Eq),
+            "gt" => Ok(Self::Gt),
+            "lt" => Ok(Self::Lt),
+            "and" => Ok(Self::And),
+            "or" => Ok(Self::Or),
+            "not" => Ok(Self::Not),
+            _ => Err(format!("Unknown arithmetic command {}", s))
+        }
+    }
+}
 
 #[derive(Debug, Clone )]
 pub enum VMInstruction {
     CReturn,
-    CArithmetic{ cmd : String },
+    CArithmetic{ cmd : ArithmeticOp },
     CLabel{ label : String  },
     CGoto{ label : String  },
     CIf{ label : String  },
-    CPush{ segment : String , value: u32 },
-    CPop{ segment : String, value: u32 },
+    CPush{ segment : Segment , value: u32 },
+    CPop{ segment : PopSegment, value: u32 },
     CFunction{ symbol : String, n_vars : u32 },
     CCall{ symbol : String, n_args : u32 },
 }
@@ -30,11 +188,113 @@ impl fmt::Display for VMInstruction {
             Self::CIf { ref label} => write!(f, "if-goto {}", label),
             Self::CFunction { ref symbol, ref n_vars} => write!(f, "function {} {}", symbol, n_vars),
             Self::CReturn => write!(f, "return"),
-            Self::CCall { ref symbol,ref n_args } => write!(f, "call {} {}", symbol, n_args)
+            Self::CCall { ref symbol,ref n_args } => write!(f, "call {} {}", symbol, n_args),
+        }
+    }
+}
+
+// What the compiler translates: either a plain VM instruction or one that the
+// optimizer fused from several. Fused forms have no VM language equivalent so
+// they never leave the translator.
+#[derive(Debug, Clone)]
+pub(crate) enum TranslatorInstruction {
+    Vm(VMInstruction),
+    // push from from_value; pop to to_value
+    Move{ from : Segment, from_value : u32, to : PopSegment, to_value : u32 },
+    // not; if-goto label
+    IfNot{ label : String },
+}
+
+impl TranslatorInstruction {
+    // the VM instructions this stands for
+    fn source(&self) -> Vec<VMInstruction> {
+        match self {
+            Self::Vm(ins) => vec![ins.clone()],
+            Self::Move { from, from_value, to, to_value } => vec![
+                VMInstruction::CPush { segment: *from, value: *from_value },
+                VMInstruction::CPop { segment: *to, value: *to_value }
+            ],
+            Self::IfNot { label } => vec![
+                VMInstruction::CArithmetic { cmd: ArithmeticOp::Not },
+                VMInstruction::CIf { label: label.clone() }
+            ]
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VmError {
+    pub filename : String,
+    pub line : usize,
+    pub text : String,
+    pub message : String
+}
+
+impl VmError {
+    pub fn new(filename : &str, line : usize, text : &str, message : &str) -> VmError {
+        VmError {
+            filename: filename.to_string(),
+            line,
+            text: text.to_string(),
+            message: message.to_string()
+        }
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // line 0 is used when the problem has no single source line
+        if self.line > 0 {
+            write!(f, "{}:{}: {}", self.filename, self.line, self.message)?;
+        } else {
+            write!(f, "{}: {}", self.filename, self.message)?;
         }
+        if !self.text.is_empty() {
+            write!(f, " - `{}`", self.text)?;
+        }
+        Ok(())
     }
 }
 
+impl std::error::Error for VmError {}
+
+// goto / if-goto may only target labels declared in the same function; returns
+// the index of each offending instruction with an error message
+fn undefined_labels(vm_instructions: &[VMInstruction]) -> Vec<(usize, String)> {
+    let mut scopes = Vec::new();
+    let mut start = 0;
+    for (index, ins) in vm_instructions.iter().enumerate() {
+        if let VMInstruction::CFunction { .. } = ins {
+            if index > start {
+                scopes.push(start..index);
+            }
+            start = index;
+        }
+    }
+    scopes.push(start..vm_instructions.len());
+
+    let mut errors = Vec::new();
+    for scope in scopes {
+        let function = match vm_instructions.get(scope.start) {
+            Some(VMInstruction::CFunction { symbol, .. }) => symbol.as_str(),
+            _ => "<top level>"
+        };
+        let labels : HashSet<&String> = vm_instructions[scope.clone()].iter().filter_map(|ins| match ins {
+            VMInstruction::CLabel { label } => Some(label),
+            _ => None
+        }).collect();
+        for index in scope {
+            match &vm_instructions[index] {
+                VMInstruction::CGoto { label } | VMInstruction::CIf { label } if !labels.contains(label) => {
+                    errors.push((index, format!("Label {} is not defined in function {}", label, function)));
+                },
+                _ => {}
+            }
+        }
+    }
+    errors
+}
+
 pub struct VMInstructionParser {
     filename : String
 }
@@ -45,98 +305,139 @@ impl VMInstructionParser {
         VMInstructionParser { filename: filename.to_string() }
     }
 
-    pub fn parse(&self) -> Result<Vec<VMInstruction>,std::io::Error> {
-        let mut f = File::open(& self.filename).expect("file not found");
+    pub fn parse(&self) -> Result<Vec<VMInstruction>,Vec<VmError>> {
+        let f = File::open(&self.filename)
+            .map_err(|e| vec![VmError::new(&self.filename, 0, "", &format!("unable to read file: {}", e))])?;
+        self.parse_reader(f)
+    }
+
+    pub fn parse_reader<R: Read>(&self, mut reader : R) -> Result<Vec<VMInstruction>,Vec<VmError>> {
         let mut contents = String::new();
-        f.read_to_string(&mut contents)?;
-        let lines = contents.to_string()
-                            .split('\r')
-                            .filter_map(|s| VMInstructionParser::read_instruction(s))
-                            .collect();
-        Ok(lines)
-    }
-
-    fn read_instruction(line : &str) -> Option<VMInstruction>{
-        let mut cs = line.trim().chars();
-        let mut done = false;
-        let mut output : Vec<char> = Vec::new();
-        let mut comment = false;
-        while !done {
-            match cs.next() {
-                Some('/') => {
-                    if comment { break };
-                    comment = true;
-                },
-                Some(c) => {
-                    comment = false;
-                    output.push(c);
+        reader.read_to_string(&mut contents)
+            .map_err(|e| vec![VmError::new(&self.filename, 0, "", &format!("unable to read file: {}", e))])?;
+        self.parse_str(&contents)
+    }
+
+    pub fn parse_str(&self, contents : &str) -> Result<Vec<VMInstruction>,Vec<VmError>> {
+        let mut instructions = Vec::new();
+        // source line of each instruction, for errors found after parsing
+        let mut lines = Vec::new();
+        let mut errors = Vec::new();
+        // accept \n, \r\n and \r line endings
+        let contents = contents.replace("\r\n", "\n");
+        let source : Vec<&str> = contents.split(['\n', '\r']).collect();
+        for (index, line) in source.iter().enumerate() {
+            match VMInstructionParser::read_instruction(line) {
+                Some(Ok(ins)) => {
+                    instructions.push(ins);
+                    lines.push(index);
                 },
-                None => {
-                    done = true;
-                }
+                Some(Err(message)) => errors.push(VmError::new(&self.filename, index + 1, line.trim(), &message)),
+                None => {}
             }
         }
-        if output.len() == 0 {
+        for (index, message) in undefined_labels(&instructions) {
+            let line = lines[index];
+            errors.push(VmError::new(&self.filename, line + 1, source[line].trim(), &message));
+        }
+        errors.sort_by_key(|error| error.line);
+        if errors.is_empty() {
+            Ok(instructions)
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn read_instruction(line : &str) -> Option<Result<VMInstruction,String>>{
+        // drop any trailing comment and collapse tabs / repeated spaces
+        let code = match line.find("//") {
+            Some(index) => &line[..index],
+            None => line
+        };
+        let ins = code.split_whitespace().collect::<Vec<&str>>().join(" ");
+        if ins.is_empty() {
             None
         } else {
-            Some(VMInstructionParser::parse_instruction(String::from_iter(output).trim().to_string()))
+            Some(VMInstructionParser::parse_instruction(ins))
         }
     }
 
-    fn parse_instruction(ins: String) -> VMInstruction {
+    fn parse_instruction(ins: String) -> Result<VMInstruction,String> {
         lazy_static! {
             // instruction reg exs
             static ref FUN0_REGEX : Regex = Regex::new(r"^(return|add|sub|neg|eq|lt|gt|and|or|not)$").unwrap();
             static ref FUN1_REGEX : Regex = Regex::new(r"^(if-goto|goto|label) (\w+)$").unwrap();
             static ref FUN2_REGEX : Regex = Regex::new(r"^(push|pop|function|call) ([\w.]+) (\d+)$").unwrap();
         }
-        if FUN2_REGEX.is_match(&ins) {
-            let captures = FUN2_REGEX.captures(&ins).unwrap();
+        if let Some(captures) = FUN2_REGEX.captures(&ins) {
             let arg1 = captures.get(2).unwrap().as_str().to_string();
-            let arg2 = captures.get(3).unwrap().as_str().parse::<u32>().unwrap();
+            let arg2 = captures.get(3).unwrap().as_str().parse::<u32>()
+                .map_err(|_| format!("Value out of range {}", captures.get(3).unwrap().as_str()))?;
             match captures.get(1).unwrap().as_str() {
-                "push" => VMInstruction::CPush{ segment: arg1, value: arg2 },
-                "pop" => VMInstruction::CPop{ segment: arg1, value: arg2 },
-                "function" => VMInstruction::CFunction { symbol: arg1, n_vars: arg2 },
-                "call" => VMInstruction::CCall { symbol:  arg1, n_args: arg2 },
-                _ => panic!("Unexpected 2 arg instruction {}", ins)
+                "push" => Ok(VMInstruction::CPush{ segment: arg1.parse()?, value: arg2 }),
+                "pop" => Ok(VMInstruction::CPop{ segment: arg1.parse()?, value: arg2 }),
+                "function" => Ok(VMInstruction::CFunction { symbol: arg1, n_vars: arg2 }),
+                "call" => Ok(VMInstruction::CCall { symbol:  arg1, n_args: arg2 }),
+                _ => Err(format!("Unexpected 2 arg instruction {}", ins))
             }
-        } else if FUN1_REGEX.is_match(&ins){
-            let captures = FUN1_REGEX.captures(&ins).unwrap();
+        } else if let Some(captures) = FUN1_REGEX.captures(&ins) {
             let label = captures.get(2).unwrap().as_str().to_string();
             match captures.get(1).unwrap().as_str() {
-                "if-goto" => VMInstruction::CIf { label },
-                "goto" => VMInstruction::CGoto { label },
-                "label" => VMInstruction::CLabel { label },
-                _ => panic!("Unexpected 2 arg instruction {}", ins)
+                "if-goto" => Ok(VMInstruction::CIf { label }),
+                "goto" => Ok(VMInstruction::CGoto { label }),
+                "label" => Ok(VMInstruction::CLabel { label }),
+                _ => Err(format!("Unexpected 1 arg instruction {}", ins))
             }
         } else if FUN0_REGEX.is_match(&ins){
             if ins == "return" {
-                VMInstruction::CReturn
+                Ok(VMInstruction::CReturn)
             } else {
-                VMInstruction::CArithmetic{ cmd: ins.clone() }
+                Ok(VMInstruction::CArithmetic{ cmd: ins.parse()? })
             }
         } else {
-            panic!("Unexpected instruction {}", ins)
+            Err("Unexpected instruction".to_string())
         }
     }
 }
 
 pub struct Compiler {
-    bool_symbol_counter : u32,
-    ret_symbol_count : u32,
-    static_base : u32
+    label_counts : HashMap<String, u32>,
+    current_function : Option<String>,
+    file_stem : String,
+    shared_routines : bool,
+    used_routines : HashSet<&'static str>,
+    optimizer : Optimizer
 }
 
 impl Compiler {
+    const CALL_ROUTINE : &'static str = "$CALL";
+    const RETURN_ROUTINE : &'static str = "$RETURN";
+    const EQ_ROUTINE : &'static str = "$EQ";
+    const GT_ROUTINE : &'static str = "$GT";
+    const LT_ROUTINE : &'static str = "$LT";
+
     pub fn new() -> Compiler {
         Compiler{ 
-            bool_symbol_counter: 0,
-            ret_symbol_count: 0,
-            static_base: 16
+            label_counts: HashMap::new(),
+            current_function: None,
+            file_stem: String::new(),
+            shared_routines: false,
+            used_routines: HashSet::new(),
+            optimizer: Optimizer::new(&[])
         }
     }
 
+    // code size mode - call, return, eq, gt and lt jump to a single shared
+    // copy of each routine (see generate_runtime) rather than being inlined
+    pub fn set_shared_routines(&mut self, shared : bool) {
+        self.shared_routines = shared;
+    }
+
+    // the passes run over each file before it is translated
+    pub fn set_optimizer(&mut self, optimizer : Optimizer) {
+        self.optimizer = optimizer;
+    }
+
     pub fn generate_bootstrap(&mut self) -> Vec<assembler::Instruction> {
         let mut init = Vec::new();
         // SP = 256
@@ -149,81 +450,164 @@ impl Compiler {
         init
     }
 
-    pub fn compile(&mut self, vm_instructions: Vec<VMInstruction>) -> Vec<assembler::Instruction> {
-        self.bool_symbol_counter = 0;
-        let instructions = vm_instructions.iter().map(|ins| self.compile_instruction(ins)).flatten().collect();
-        // update static base
-        let static_count = vm_instructions.iter().filter_map(|ins| {
-            match ins  {
-                VMInstruction::CPush{ segment, value } if segment == "static" => Some(*value + 1),
-                VMInstruction::CPop{ segment, value } if segment == "static" => Some(*value + 1),              
-                _ => Some(0)
+    pub fn compile(&mut self, filename: &str, vm_instructions: Vec<VMInstruction>) -> Result<Vec<assembler::Instruction>, Vec<VmError>> {
+        // the parser reports these with line numbers, this catches instructions built in code
+        let errors : Vec<VmError> = undefined_labels(&vm_instructions).into_iter()
+            .map(|(index, message)| VmError::new(filename, 0, &vm_instructions[index].to_string(), &message))
+            .collect();
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        self.current_function = None;
+        // statics are named after the file they are declared in
+        self.file_stem = Path::new(filename).file_stem().and_then(|s| s.to_str()).unwrap_or(filename).to_string();
+        let instructions = self.optimizer.optimize(vm_instructions).iter().map(|ins| self.compile_instruction(ins)).flatten().collect();
+        Ok(instructions)
+    }
+
+    // static i in File.vm becomes the assembler variable File.i
+    fn static_symbol(&self, value : u32) -> String {
+        format!("{}.{}", self.file_stem, value)
+    }
+
+    // labels are scoped to the enclosing function as Function$label
+    fn scoped_label(&self, label : &str) -> String {
+        match self.current_function {
+            Some(ref function) => format!("{}${}", function, label),
+            None => label.to_string()
+        }
+    }
+
+    // generates a fresh Function$name.N label, numbered per function
+    fn unique_label(&mut self, name : &str) -> String {
+        let prefix = self.scoped_label(name);
+        let count = self.label_counts.entry(prefix.clone()).or_insert(0);
+        let label = format!("{}.{}", prefix, count);
+        *count += 1;
+        label
+    }
+
+    fn lookup_segment_target(&mut self, segment: &Segment) -> String {
+        match segment {
+            Segment::Local => "LCL".to_string(),
+            Segment::Argument => "ARG".to_string(),
+            Segment::This => "THIS".to_string(),
+            Segment::That => "THAT".to_string(),
+            Segment::Temp => "5".to_string(),
+            Segment::Pointer => "3".to_string(),
+            Segment::Static | Segment::Constant => panic!("Unsupported segment {}", segment)
+        }
+    }
+
+    // @target, using a numeric A instruction for the fixed temp / pointer bases
+    fn segment_base(target : &str) -> Instruction {
+        match target.parse::<u32>() {
+            Ok(value) => Instruction::AInstruction { symbol: None, value: Some(value) },
+            Err(_) => Instruction::AInstruction { symbol: Some(target.to_string()), value: None }
+        }
+    }
+
+    // D = segment[value]
+    fn load_d(&mut self, segment : &Segment, value : u32, output : &mut Vec<Instruction>) {
+        match segment {
+            Segment::Constant => {
+                Compiler::load_value(value, output);
+            },
+            Segment::Static => {
+                output.push(Instruction::AInstruction { symbol: Some(self.static_symbol(value)), value: None });
+                output.push(Instruction::CInstruction { dest: Some("D".to_string()), comp:"M".to_string(), jump: None });
+            },
+            _ => {
+                let target = self.lookup_segment_target(segment);
+                output.push(Compiler::segment_base(&target));
+                match target.as_str() {
+                    "LCL" | "ARG" | "THIS" | "THAT" => {
+                        output.push(Instruction::CInstruction { dest: Some("A".to_string()), comp:"M".to_string(), jump: None });
+                    },
+                    _ => {}
+                }
+                if value > 0 {
+                    output.push(Instruction::CInstruction { dest: Some("D".to_string()), comp:"A".to_string(), jump: None });
+                    output.push(Instruction::AInstruction { symbol: None, value: Some(value) });
+                    output.push(Instruction::CInstruction { dest: Some("A".to_string()), comp:"D+A".to_string(), jump: None });
+                }
+                output.push(Instruction::CInstruction { dest: Some("D".to_string()), comp:"M".to_string(), jump: None });
             }
-        }).max().unwrap();
-        self.static_base += static_count;
-        instructions
+        }
+    }
+
+    // R13 = address of segment[value]
+    fn address_to_r13(&mut self, segment : PopSegment, value : u32, output : &mut Vec<Instruction>) {
+        let target = self.lookup_segment_target(&segment.into());
+        output.push(Compiler::segment_base(&target));
+        match target.as_str() {
+            "LCL" | "ARG" | "THIS" | "THAT" => {
+                output.push(Instruction::CInstruction { dest: Some("A".to_string()), comp:"M".to_string(), jump: None });
+            },
+            _ => {}
+        }
+        output.push(Instruction::CInstruction { dest: Some("D".to_string()), comp:"A".to_string(), jump: None });
+        if value > 0 {
+            output.push(Instruction::AInstruction { symbol: None, value: Some(value) });
+            output.push(Instruction::CInstruction { dest: Some("D".to_string()), comp:"D+A".to_string(), jump: None });
+        }
+        output.push(Instruction::AInstruction { symbol: Some("R13".to_string()), value: None });
+        output.push(Instruction::CInstruction { dest: Some("M".to_string()), comp:"D".to_string(), jump: None });
     }
 
-    fn lookup_segment_target(&mut self, segment: &String) -> String {
-        match segment.as_str() {
-            "local" => "LCL".to_string(),
-            "argument" => "ARG".to_string(),
-            "this" => "THIS".to_string(),
-            "that" => "THAT".to_string(),
-            "temp" => "5".to_string(),
-            "static" => { self.static_base.to_string() },
-            "pointer" => "3".to_string(),
-            _ => panic!("Unsupported segment {}", segment)
+    fn compile_instruction(&mut self, ins : &TranslatorInstruction) -> Vec<Instruction> {
+        if let TranslatorInstruction::Vm(ins) = ins {
+            return self.compile_vm_instruction(ins);
+        }
+        // fused instructions are commented with the VM code they replace
+        let mut output : Vec<Instruction> = ins.source().iter().map(|vm| Instruction::Comment { contents: format!("{}", vm) }).collect();
+        match ins {
+            TranslatorInstruction::Vm(_) => {},
+            TranslatorInstruction::Move{ from, from_value, to: PopSegment::Static, to_value } => {
+                self.load_d(from, *from_value, &mut output);
+                output.push(Instruction::AInstruction { symbol: Some(self.static_symbol(*to_value)), value: None });
+                output.push(Instruction::CInstruction { dest: Some("M".to_string()), comp:"D".to_string(), jump: None });
+            },
+            TranslatorInstruction::Move{ from, from_value, to, to_value } => {
+                self.address_to_r13(*to, *to_value, &mut output);
+                self.load_d(from, *from_value, &mut output);
+                output.push(Instruction::AInstruction { symbol: Some("R13".to_string()), value: None });
+                output.push(Instruction::CInstruction { dest: Some("A".to_string()), comp:"M".to_string(), jump: None });
+                output.push(Instruction::CInstruction { dest: Some("M".to_string()), comp:"D".to_string(), jump: None });
+            },
+            TranslatorInstruction::IfNot { label } => {
+                // jump unless the value is true (-1), i.e. when `not value` is non zero
+                Compiler::pop_d(&mut output);
+                output.push(Instruction::AInstruction { symbol: Some(self.scoped_label(label)), value: None });
+                output.push(Instruction::CInstruction { dest: None, comp:"D+1".to_string(), jump: Some("JNE".to_string()) });
+            }
         }
+        output
     }
 
-    fn compile_instruction(&mut self, ins : &VMInstruction) -> Vec<Instruction> {
+    fn compile_vm_instruction(&mut self, ins : &VMInstruction) -> Vec<Instruction> {
         let mut output = Vec::new();
         output.push(Instruction::Comment { contents: format!("{}", ins) });
         match ins {
             VMInstruction::CPush{ segment, value } => {
-                match segment.as_str() {
-                    "constant" => {
+                match segment {
+                    Segment::Constant => {
                         Compiler::push_value(*value, &mut output);
                     },
+                    Segment::Static => {
+                        Compiler::push_symbol(&self.static_symbol(*value), &mut output);
+                    },
                     _ => {
-                        let target = self.lookup_segment_target(segment);
-                        output.push(Instruction::AInstruction { symbol: Some(target.clone()), value: None });                    
-                        match target.as_str() {
-                            "LCL" | "ARG" | "THIS" | "THAT" => {
-                                output.push(Instruction::CInstruction { dest: Some("A".to_string()), comp:"M".to_string(), jump: None });
-                            },
-                            _ => {}
-                        }
-                        if *value > 0 {
-                            output.push(Instruction::CInstruction { dest: Some("D".to_string()), comp:"A".to_string(), jump: None });
-                            output.push(Instruction::AInstruction { symbol: None, value: Some(*value) });
-                            output.push(Instruction::CInstruction { dest: Some("A".to_string()), comp:"D+A".to_string(), jump: None });
-                        }
-                        output.push(Instruction::CInstruction { dest: Some("D".to_string()), comp:"M".to_string(), jump: None });                    
+                        self.load_d(segment, *value, &mut output);
                         Compiler::push_d(&mut output);            
                     }
                 }
             }, 
+            VMInstruction::CPop{ segment: PopSegment::Static, value } => {
+                Compiler::pop_symbol(&self.static_symbol(*value), &mut output);
+            },
             VMInstruction::CPop{ segment, value } => {
-                let target = self.lookup_segment_target(segment);
-                        
-                output.push(Instruction::AInstruction { symbol: Some(target.to_string()), value: None });                    
-                match target.as_str() {
-                    "LCL" | "ARG" | "THIS" | "THAT" => {
-                        output.push(Instruction::CInstruction { dest: Some("A".to_string()), comp:"M".to_string(), jump: None });
-                    },
-                    _ => {}
-                }
-                output.push(Instruction::CInstruction { dest: Some("D".to_string()), comp:"A".to_string(), jump: None });
-                if *value > 0 {
-                    output.push(Instruction::AInstruction { symbol: None, value: Some(*value) });
-                    output.push(Instruction::CInstruction { dest: Some("D".to_string()), comp:"D+A".to_string(), jump: None });
-                }
-
-                output.push(Instruction::AInstruction { symbol: Some("R13".to_string()), value: None });
-                output.push(Instruction::CInstruction { dest: Some("M".to_string()), comp:"D".to_string(), jump: None });
-
+                self.address_to_r13(*segment, *value, &mut output);
                 //Pop the current stack value into the address at R13
                 Compiler::pop_d(&mut output);
                 output.push(Instruction::AInstruction { symbol: Some("R13".to_string()), value: None });
@@ -231,98 +615,106 @@ impl Compiler {
                 output.push(Instruction::CInstruction { dest: Some("M".to_string()), comp:"D".to_string(), jump: None });            
             },
             VMInstruction::CArithmetic { cmd } => {
-                match cmd.as_str() {
-                    "sub" => { Compiler::sub(&mut output); },
-                    "add"=> { Compiler::add(&mut output); },
-                    "and"=> { Compiler::and(&mut output); },
-                    "or"=> { Compiler::or(&mut output); },
-                    "not"=> { Compiler::not(&mut output); },
-                    "neg"=> { Compiler::neg(&mut output); },
-                    "lt" => { self.lt(&mut output) },
-                    "gt" => { self.gt(&mut output) },
-                    "eq" => { self.eq(&mut output) },
-                    _ => panic!("Unexpected arithmetic cmd {}", cmd)
+                match cmd {
+                    ArithmeticOp::Sub => { Compiler::sub(&mut output); },
+                    ArithmeticOp::Add => { Compiler::add(&mut output); },
+                    ArithmeticOp::And => { Compiler::and(&mut output); },
+                    ArithmeticOp::Or => { Compiler::or(&mut output); },
+                    ArithmeticOp::Not => { Compiler::not(&mut output); },
+                    ArithmeticOp::Neg => { Compiler::neg(&mut output); },
+                    ArithmeticOp::Lt => { self.lt(&mut output) },
+                    ArithmeticOp::Gt => { self.gt(&mut output) },
+                    ArithmeticOp::Eq => { self.eq(&mut output) }
                 }
             },
             VMInstruction::CLabel { label } => {
-                output.push(Instruction::LInstruction { symbol: label.to_string() });
+                output.push(Instruction::LInstruction { symbol: self.scoped_label(label) });
             },
             VMInstruction::CGoto { label } => {
-                output.push(Instruction::AInstruction { symbol: Some(label.to_string()), value: None });
+                output.push(Instruction::AInstruction { symbol: Some(self.scoped_label(label)), value: None });
                 output.push(Instruction::CInstruction { dest: None, comp: "0".to_string(), jump: Some("JMP".to_string()) })                            
             },
             VMInstruction::CIf { label } => {
                 Compiler::pop_d(&mut output);
-                output.push(Instruction::AInstruction { symbol: Some(label.to_string()), value: None });
+                output.push(Instruction::AInstruction { symbol: Some(self.scoped_label(label)), value: None });
                 output.push(Instruction::CInstruction { dest: None, comp:"D".to_string(), jump: Some("JNE".to_string()) });
             },
             VMInstruction::CCall { symbol, n_args } => {
                 self.call(symbol, *n_args, &mut output);                             
             },
             VMInstruction::CFunction { symbol, n_vars } => {
+                self.current_function = Some(symbol.to_string());
                 output.push(Instruction::LInstruction { symbol: symbol.to_string() });
                 for _ in 0..*n_vars {
                     Compiler::push_value(0, &mut output);
                 }
             },
             VMInstruction::CReturn => {
-                
-                // @R13 = LCL - 5
-                Compiler::assign("R13", "LCL", &mut output);    
-                output.push(Instruction::AInstruction { symbol: None, value: Some(5) });
-                output.push(Instruction::CInstruction { dest: Some("D".to_string()), comp:"A".to_string(), jump: None });                 
-                output.push(Instruction::AInstruction { symbol: Some("R13".to_string()), value: None });
-                output.push(Instruction::CInstruction { dest: Some("M".to_string()), comp:"M-D".to_string(), jump: None });                 
-                // save ret address in R14 
-                // retAddr = *(LCL - 5)
-                Compiler::assign_deref("R14", "R13", &mut output);    
-                                                                
-                // pop stack value onto current location of ARG
-                Compiler::pop_d(&mut output);
-                output.push(Instruction::AInstruction { symbol: Some("ARG".to_string()), value: None });
-                output.push(Instruction::CInstruction { dest: Some("A".to_string()), comp:"M".to_string(), jump: None }); 
-                output.push(Instruction::CInstruction { dest: Some("M".to_string()), comp:"D".to_string(), jump: None });                 
-                
-                // set SP = *ARG + 1
-                output.push(Instruction::AInstruction { symbol: Some("ARG".to_string()), value: None });
-                output.push(Instruction::CInstruction { dest: Some("A".to_string()), comp:"M".to_string(), jump: None }); 
-                output.push(Instruction::CInstruction { dest: Some("D".to_string()), comp:"A+1".to_string(), jump: None }); 
-                output.push(Instruction::AInstruction { symbol: Some("SP".to_string()), value: None });
-                output.push(Instruction::CInstruction { dest: Some("M".to_string()), comp:"D".to_string(), jump: None });                 
-                
-                // Restore LCL
-                // LCL = *(@R13 - 4)
-                output.push(Instruction::AInstruction { symbol: Some("R13".to_string()), value: None });
-                output.push(Instruction::CInstruction { dest: Some("M".to_string()), comp:"M+1".to_string(), jump: None });                 
-                Compiler::assign_deref("LCL", "R13", &mut output);                
-                
-                // Restore ARG
-                // ARG = *(@R13 - 3)
-                output.push(Instruction::AInstruction { symbol: Some("R13".to_string()), value: None });
-                output.push(Instruction::CInstruction { dest: Some("M".to_string()), comp:"M+1".to_string(), jump: None });                 
-                Compiler::assign_deref("ARG", "R13", &mut output);                
-                
-                // Restore THIS
-                // THIS = *(@R13 - 2)
-                output.push(Instruction::AInstruction { symbol: Some("R13".to_string()), value: None });
-                output.push(Instruction::CInstruction { dest: Some("M".to_string()), comp:"M+1".to_string(), jump: None });                 
-                Compiler::assign_deref("THIS", "R13", &mut output);                
-                
-                // Restore THAT
-                // THAT = *(@R13 - 1)
-                output.push(Instruction::AInstruction { symbol: Some("R13".to_string()), value: None });
-                output.push(Instruction::CInstruction { dest: Some("M".to_string()), comp:"M+1".to_string(), jump: None });
-                Compiler::assign_deref("THAT", "R13", &mut output);                
-                  
-                // goto retAddr = *(@R13 - 5)
-                output.push(Instruction::AInstruction { symbol: Some("R14".to_string()), value: None });
-                output.push(Instruction::CInstruction { dest: Some("A".to_string()), comp:"M".to_string(), jump: None });                 
-                output.push(Instruction::CInstruction { dest: None, comp:"0".to_string(), jump: Some("JMP".to_string()) });                
+                if self.shared_routines {
+                    self.used_routines.insert(Compiler::RETURN_ROUTINE);
+                    Compiler::goto_label(Compiler::RETURN_ROUTINE, &mut output);
+                } else {
+                    Compiler::return_frame(&mut output);
+                }
             }
         }
         output
     }
 
+    fn return_frame(output : &mut Vec<Instruction>) {
+        // @R13 = LCL - 5
+        Compiler::assign("R13", "LCL", output);    
+        output.push(Instruction::AInstruction { symbol: None, value: Some(5) });
+        output.push(Instruction::CInstruction { dest: Some("D".to_string()), comp:"A".to_string(), jump: None });                 
+        output.push(Instruction::AInstruction { symbol: Some("R13".to_string()), value: None });
+        output.push(Instruction::CInstruction { dest: Some("M".to_string()), comp:"M-D".to_string(), jump: None });                 
+        // save ret address in R14 
+        // retAddr = *(LCL - 5)
+        Compiler::assign_deref("R14", "R13", output);    
+                                                        
+        // pop stack value onto current location of ARG
+        Compiler::pop_d(output);
+        output.push(Instruction::AInstruction { symbol: Some("ARG".to_string()), value: None });
+        output.push(Instruction::CInstruction { dest: Some("A".to_string()), comp:"M".to_string(), jump: None }); 
+        output.push(Instruction::CInstruction { dest: Some("M".to_string()), comp:"D".to_string(), jump: None });                 
+        
+        // set SP = *ARG + 1
+        output.push(Instruction::AInstruction { symbol: Some("ARG".to_string()), value: None });
+        output.push(Instruction::CInstruction { dest: Some("A".to_string()), comp:"M".to_string(), jump: None }); 
+        output.push(Instruction::CInstruction { dest: Some("D".to_string()), comp:"A+1".to_string(), jump: None }); 
+        output.push(Instruction::AInstruction { symbol: Some("SP".to_string()), value: None });
+        output.push(Instruction::CInstruction { dest: Some("M".to_string()), comp:"D".to_string(), jump: None });                 
+        
+        // Restore LCL
+        // LCL = *(@R13 - 4)
+        output.push(Instruction::AInstruction { symbol: Some("R13".to_string()), value: None });
+        output.push(Instruction::CInstruction { dest: Some("M".to_string()), comp:"M+1".to_string(), jump: None });                 
+        Compiler::assign_deref("LCL", "R13", output);                
+        
+        // Restore ARG
+        // ARG = *(@R13 - 3)
+        output.push(Instruction::AInstruction { symbol: Some("R13".to_string()), value: None });
+        output.push(Instruction::CInstruction { dest: Some("M".to_string()), comp:"M+1".to_string(), jump: None });                 
+        Compiler::assign_deref("ARG", "R13", output);                
+        
+        // Restore THIS
+        // THIS = *(@R13 - 2)
+        output.push(Instruction::AInstruction { symbol: Some("R13".to_string()), value: None });
+        output.push(Instruction::CInstruction { dest: Some("M".to_string()), comp:"M+1".to_string(), jump: None });                 
+        Compiler::assign_deref("THIS", "R13", output);                
+        
+        // Restore THAT
+        // THAT = *(@R13 - 1)
+        output.push(Instruction::AInstruction { symbol: Some("R13".to_string()), value: None });
+        output.push(Instruction::CInstruction { dest: Some("M".to_string()), comp:"M+1".to_string(), jump: None });
+        Compiler::assign_deref("THAT", "R13", output);                
+          
+        // goto retAddr = *(@R13 - 5)
+        output.push(Instruction::AInstruction { symbol: Some("R14".to_string()), value: None });
+        output.push(Instruction::CInstruction { dest: Some("A".to_string()), comp:"M".to_string(), jump: None });                 
+        output.push(Instruction::CInstruction { dest: None, comp:"0".to_string(), jump: Some("JMP".to_string()) });
+    }
+
     fn push_symbol(symbol : &str, output : &mut Vec<Instruction>) {
         output.push(Instruction::Comment { contents: format!("Push {}", symbol) });
         output.push(Instruction::AInstruction { symbol: Some(symbol.to_string()), value: None });
@@ -332,11 +724,23 @@ impl Compiler {
 
     fn push_value(value : u32, output : &mut Vec<Instruction>) {
         output.push(Instruction::Comment { contents: format!("Push {}", value) });
-        output.push(Instruction::AInstruction { symbol: None, value: Some(value) });
-        output.push(Instruction::CInstruction { dest: Some("D".to_string()), comp:"A".to_string(), jump: None }); 
+        Compiler::load_value(value, output);
         Compiler::push_d(output);
     }
 
+    // D = value as a 16 bit word
+    fn load_value(value : u32, output : &mut Vec<Instruction>) {
+        let word = value & 0xFFFF;
+        if word > 0x7FFF {
+            // A instructions only hold 15 bits, so load the complement and invert it
+            output.push(Instruction::AInstruction { symbol: None, value: Some(0xFFFF - word) });
+            output.push(Instruction::CInstruction { dest: Some("D".to_string()), comp:"!A".to_string(), jump: None });
+        } else {
+            output.push(Instruction::AInstruction { symbol: None, value: Some(word) });
+            output.push(Instruction::CInstruction { dest: Some("D".to_string()), comp:"A".to_string(), jump: None });
+        }
+    }
+
     fn push_d(output : &mut Vec<Instruction>) {
         //Push the value in D
         output.push(Instruction::AInstruction { symbol: Some("SP".to_string()), value: None });
@@ -435,7 +839,24 @@ impl Compiler {
     pub fn call(&mut self, symbol: &str, n_args : u32, output : &mut Vec<Instruction>) {
         output.push(Instruction::Comment { contents: format!("call {} {}", symbol, n_args).to_string() });
         
-        let return_label = format!("ret_{}", self.ret_symbol_count);
+        let return_label = self.unique_label("ret");
+        if self.shared_routines {
+            // R13 = nArgs, R14 = function, D = return address
+            self.used_routines.insert(Compiler::CALL_ROUTINE);
+            output.push(Instruction::AInstruction { symbol: None, value: Some(n_args) });
+            output.push(Instruction::CInstruction { dest: Some("D".to_string()), comp:"A".to_string(), jump: None });
+            output.push(Instruction::AInstruction { symbol: Some("R13".to_string()), value: None });
+            output.push(Instruction::CInstruction { dest: Some("M".to_string()), comp:"D".to_string(), jump: None });
+            output.push(Instruction::AInstruction { symbol: Some(symbol.to_string()), value: None });
+            output.push(Instruction::CInstruction { dest: Some("D".to_string()), comp:"A".to_string(), jump: None });
+            output.push(Instruction::AInstruction { symbol: Some("R14".to_string()), value: None });
+            output.push(Instruction::CInstruction { dest: Some("M".to_string()), comp:"D".to_string(), jump: None });
+            output.push(Instruction::AInstruction { symbol: Some(return_label.clone()), value: None });
+            output.push(Instruction::CInstruction { dest: Some("D".to_string()), comp:"A".to_string(), jump: None });
+            Compiler::goto_label(Compiler::CALL_ROUTINE, output);
+            output.push(Instruction::LInstruction { symbol: return_label });
+            return;
+        }
         output.push(Instruction::Comment { contents: format!("push {}", return_label).to_string() });
         output.push(Instruction::AInstruction { symbol: Some(return_label.clone()), value: None });
         output.push(Instruction::CInstruction { dest: Some("D".to_string()), comp:"A".to_string(), jump: None }); 
@@ -458,22 +879,92 @@ impl Compiler {
         Compiler::goto_label(symbol, output);
         // return label
         output.push(Instruction::LInstruction { symbol: return_label.clone() });
-        self.ret_symbol_count += 1;  
     }
 
     fn lt(&mut self, output : &mut Vec<Instruction>){
-        self.boolean_cmd("JLT", output);
+        self.compare("JLT", Compiler::LT_ROUTINE, output);
     } 
     
     fn gt(&mut self, output : &mut Vec<Instruction>){
-        self.boolean_cmd("JGT", output);
+        self.compare("JGT", Compiler::GT_ROUTINE, output);
     } 
     
     fn eq(&mut self, output : &mut Vec<Instruction>){
-        self.boolean_cmd("JEQ", output);
+        self.compare("JEQ", Compiler::EQ_ROUTINE, output);
     } 
+
+    fn compare(&mut self, jmp_cmd: &str, routine: &'static str, output: &mut Vec<Instruction>){
+        if !self.shared_routines {
+            self.boolean_cmd(jmp_cmd, output);
+            return;
+        }
+        // D = return address
+        self.used_routines.insert(routine);
+        let return_label = self.unique_label("ret");
+        output.push(Instruction::AInstruction { symbol: Some(return_label.clone()), value: None });
+        output.push(Instruction::CInstruction { dest: Some("D".to_string()), comp:"A".to_string(), jump: None });
+        Compiler::goto_label(routine, output);
+        output.push(Instruction::LInstruction { symbol: return_label });
+    }
+
+    // the shared routines used by code compiled so far, to be placed after
+    // the program. Guarded by a halt loop so straight line programs (no
+    // bootstrap) never fall through into them.
+    pub fn generate_runtime(&mut self) -> Vec<Instruction> {
+        let mut output = Vec::new();
+        if self.used_routines.is_empty() {
+            return output;
+        }
+        self.current_function = None;
+        output.push(Instruction::LInstruction { symbol: "$HALT".to_string() });
+        Compiler::goto_label("$HALT", &mut output);
+
+        if self.used_routines.contains(Compiler::CALL_ROUTINE) {
+            output.push(Instruction::LInstruction { symbol: Compiler::CALL_ROUTINE.to_string() });
+            // push return address held in D
+            Compiler::push_d(&mut output);
+            Compiler::push_symbol("LCL", &mut output);
+            Compiler::push_symbol("ARG", &mut output);
+            Compiler::push_symbol("THIS", &mut output);
+            Compiler::push_symbol("THAT", &mut output);
+            // ARG = SP - 5 - nArgs
+            output.push(Instruction::AInstruction { symbol: Some("R13".to_string()), value: None });
+            output.push(Instruction::CInstruction { dest: Some("D".to_string()), comp:"M".to_string(), jump: None });
+            output.push(Instruction::AInstruction { symbol: None, value: Some(5) });
+            output.push(Instruction::CInstruction { dest: Some("D".to_string()), comp:"D+A".to_string(), jump: None });
+            output.push(Instruction::AInstruction { symbol: Some("SP".to_string()), value: None });
+            output.push(Instruction::CInstruction { dest: Some("D".to_string()), comp:"M-D".to_string(), jump: None });
+            output.push(Instruction::AInstruction { symbol: Some("ARG".to_string()), value: None });
+            output.push(Instruction::CInstruction { dest: Some("M".to_string()), comp:"D".to_string(), jump: None });
+            // LCL = SP
+            Compiler::assign("LCL", "SP", &mut output);
+            // goto function held in R14
+            output.push(Instruction::AInstruction { symbol: Some("R14".to_string()), value: None });
+            output.push(Instruction::CInstruction { dest: Some("A".to_string()), comp:"M".to_string(), jump: None });
+            output.push(Instruction::CInstruction { dest: None, comp:"0".to_string(), jump: Some("JMP".to_string()) });
+        }
+        if self.used_routines.contains(Compiler::RETURN_ROUTINE) {
+            output.push(Instruction::LInstruction { symbol: Compiler::RETURN_ROUTINE.to_string() });
+            Compiler::return_frame(&mut output);
+        }
+        for (routine, jmp_cmd) in [(Compiler::EQ_ROUTINE, "JEQ"), (Compiler::GT_ROUTINE, "JGT"), (Compiler::LT_ROUTINE, "JLT")] {
+            if self.used_routines.contains(routine) {
+                output.push(Instruction::LInstruction { symbol: routine.to_string() });
+                // save return address held in D
+                output.push(Instruction::AInstruction { symbol: Some("R15".to_string()), value: None });
+                output.push(Instruction::CInstruction { dest: Some("M".to_string()), comp:"D".to_string(), jump: None });
+                self.boolean_cmd(jmp_cmd, &mut output);
+                output.push(Instruction::AInstruction { symbol: Some("R15".to_string()), value: None });
+                output.push(Instruction::CInstruction { dest: Some("A".to_string()), comp:"M".to_string(), jump: None });
+                output.push(Instruction::CInstruction { dest: None, comp:"0".to_string(), jump: Some("JMP".to_string()) });
+            }
+        }
+        output
+    }
     
     fn boolean_cmd(&mut self, jmp_cmd: &str, output: &mut Vec<Instruction>){
+        let true_label = self.unique_label("BOOL");
+        let end_label = format!("{}.END", true_label);
         // grab top value off the stack
         output.push(Instruction::AInstruction { symbol: Some("SP".to_string()), value: None });
         output.push(Instruction::CInstruction { dest: Some("A".to_string()), comp:"M".to_string(), jump: None });
@@ -483,23 +974,22 @@ impl Compiler {
         output.push(Instruction::CInstruction { dest: Some("D".to_string()), comp:"M".to_string(), jump: None });
         output.push(Instruction::CInstruction { dest: Some("A".to_string()), comp:"A-1".to_string(), jump: None });
         output.push(Instruction::CInstruction { dest: Some("D".to_string()), comp:"M-D".to_string(), jump: None });
-        output.push(Instruction::AInstruction { symbol: Some(format!("BOOL_{}", self.bool_symbol_counter)), value: None });
+        output.push(Instruction::AInstruction { symbol: Some(true_label.clone()), value: None });
         output.push(Instruction::CInstruction { dest: None, comp:"D".to_string(), jump: Some(jmp_cmd.to_string()) });
         output.push(Instruction::AInstruction { symbol: Some("SP".to_string()), value: None });                
         output.push(Instruction::CInstruction { dest: Some("A".to_string()), comp:"M".to_string(), jump: None });
         output.push(Instruction::CInstruction { dest: Some("A".to_string()), comp:"A-1".to_string(), jump: None });
         output.push(Instruction::CInstruction { dest: Some("A".to_string()), comp:"A-1".to_string(), jump: None });
         output.push(Instruction::CInstruction { dest: Some("M".to_string()), comp:"0".to_string(), jump: None });
-        output.push(Instruction::AInstruction { symbol: Some(format!("END_BOOL_{}", self.bool_symbol_counter)), value: None });
+        output.push(Instruction::AInstruction { symbol: Some(end_label.clone()), value: None });
         output.push(Instruction::CInstruction { dest: None, comp:"0".to_string(), jump: Some("JMP".to_string()) });
-        output.push(Instruction::LInstruction { symbol: format!("BOOL_{}", self.bool_symbol_counter) });
+        output.push(Instruction::LInstruction { symbol: true_label });
         output.push(Instruction::AInstruction { symbol: Some("SP".to_string()), value: None });                
         output.push(Instruction::CInstruction { dest: Some("A".to_string()), comp:"M".to_string(), jump: None });
         output.push(Instruction::CInstruction { dest: Some("A".to_string()), comp:"A-1".to_string(), jump: None });
         output.push(Instruction::CInstruction { dest: Some("A".to_string()), comp:"A-1".to_string(), jump: None });
         output.push(Instruction::CInstruction { dest: Some("M".to_string()), comp:"-1".to_string(), jump: None });
-        output.push(Instruction::LInstruction { symbol: format!("END_BOOL_{}", self.bool_symbol_counter) });
-        self.bool_symbol_counter += 1;                        
+        output.push(Instruction::LInstruction { symbol: end_label });
         // decrement SP
         output.push(Instruction::AInstruction { symbol: Some("SP".to_string()), value: None });
         output.push(Instruction::CInstruction { dest: Some("M".to_string()), comp:"M-1".to_string(), jump: None });
@@ -517,9 +1007,9 @@ impl ASMWriter {
         }
     }
 
-    pub fn write(&self, ins : &Vec<Instruction>) {
-        let mut f = File::create(&self.filename).expect("unable to create file");
+    pub fn write(&self, ins : &Vec<Instruction>) -> std::io::Result<()> {
+        let mut f = File::create(&self.filename)?;
         let asm : Vec<String> = ins.iter().map(|i| i.to_string()).collect();
-        write!(f, "{}", asm.join("\r")).expect("Failed to write ASM to file")
+        write!(f, "{}", asm.join("\r"))
     }
 }
\ No newline at end of file
@@ -1,57 +1,283 @@
 use std::env;
-use std::path::{Path};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process;
 // use std::io::Write;
 use assembler::{ Assembler, AssemblyWriter, Instruction };
-use vmtranslator::{Compiler, VMInstructionParser, ASMWriter};
+use vmtranslator::{Compiler, VMInstruction, VMInstructionParser, ASMWriter, VmError};
+use vmtranslator::optimizer::{DeadFunctionEliminator, Inliner, Optimizer, Pass};
 
-fn write_asm(filestem : &str, instructions : &Vec<Instruction>) {
+const USAGE : &str = "usage: vmtranslator <file.vm | dir> [target] [--no-bootstrap] [--shared-routines] [--optimize all|<pass>,...] [--inline <max size>] [--eliminate-dead-code] [--entry <function>] [-o | --output <path>] [--emit asm|hack|both]";
+
+#[derive(PartialEq)]
+enum Emit {
+    Asm,
+    Hack,
+    Both
+}
+
+struct Options {
+    input : PathBuf,
+    target : Option<String>,
+    output : Option<PathBuf>,
+    bootstrap : bool,
+    shared_routines : bool,
+    passes : Vec<Pass>,
+    inline_size : Option<usize>,
+    eliminate_dead_code : bool,
+    entry_point : String,
+    emit : Emit
+}
+
+impl Options {
+    fn parse(args : &[String]) -> Result<Options, String> {
+        let mut positional = Vec::new();
+        let mut output = None;
+        let mut bootstrap = true;
+        let mut shared_routines = false;
+        let mut passes = Vec::new();
+        let mut inline_size = None;
+        let mut eliminate_dead_code = false;
+        let mut entry_point = "Sys.init".to_string();
+        let mut emit = Emit::Both;
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--no-bootstrap" => bootstrap = false,
+                "--shared-routines" => shared_routines = true,
+                "--eliminate-dead-code" => eliminate_dead_code = true,
+                "--entry" => {
+                    entry_point = iter.next().ok_or(format!("missing value for {}", arg))?.clone();
+                },
+                "--optimize" => {
+                    passes = match iter.next().map(|s| s.as_str()) {
+                        Some("all") => Optimizer::ALL.to_vec(),
+                        Some(names) => names.split(',').map(|name| name.parse()).collect::<Result<Vec<Pass>, String>>()?,
+                        None => return Err("missing value for --optimize".to_string())
+                    };
+                },
+                "--inline" => {
+                    let size = iter.next().ok_or(format!("missing value for {}", arg))?;
+                    inline_size = Some(size.parse().map_err(|_| format!("invalid value for --inline: {}", size))?);
+                },
+                "-o" | "--output" => {
+                    let path = iter.next().ok_or(format!("missing value for {}", arg))?;
+                    output = Some(PathBuf::from(path));
+                },
+                "--emit" => {
+                    emit = match iter.next().map(|s| s.as_str()) {
+                        Some("asm") => Emit::Asm,
+                        Some("hack") => Emit::Hack,
+                        Some("both") => Emit::Both,
+                        other => return Err(format!("invalid value for --emit: {}", other.unwrap_or("<missing>")))
+                    };
+                },
+                flag if flag.starts_with('-') => return Err(format!("unknown option {}", flag)),
+                _ => positional.push(arg.clone())
+            }
+        }
+        if positional.is_empty() || positional.len() > 2 {
+            return Err("expected an input path and an optional target name".to_string());
+        }
+        let input = PathBuf::from(&positional[0]);
+        if !input.exists() {
+            return Err(format!("File {:?} not found", input));
+        }
+        Ok(Options { input, target: positional.get(1).cloned(), output, bootstrap, shared_routines, passes, inline_size, eliminate_dead_code, entry_point, emit })
+    }
+
+    // output file stem, without the .asm / .hack extension
+    fn output_stem(&self) -> PathBuf {
+        if let Some(ref output) = self.output {
+            return match output.extension().and_then(|e| e.to_str()) {
+                Some("asm") | Some("hack") => output.with_extension(""),
+                _ => output.clone()
+            };
+        }
+        let dir = if self.input.is_file() { self.input.parent().unwrap() } else { self.input.as_path() };
+        let name = match (&self.target, self.input.is_file()) {
+            (Some(target), _) => PathBuf::from(target),
+            (None, true) => PathBuf::from(self.input.file_stem().unwrap()),
+            (None, false) => {
+                let full_path = self.input.canonicalize().unwrap_or_else(|_| self.input.clone());
+                PathBuf::from(full_path.file_name().unwrap_or(OsStr::new("out")))
+            }
+        };
+        dir.join(name)
+    }
+}
+
+// a path as a string, for the parser and the writers
+fn path_str(path : &Path) -> Result<&str, Vec<VmError>> {
+    path.to_str().ok_or_else(|| vec![VmError::new(&path.to_string_lossy(), 0, "", "path is not valid UTF-8")])
+}
+
+fn write_error(filename : &str, e : std::io::Error) -> Vec<VmError> {
+    vec![VmError::new(filename, 0, "", &format!("unable to write file: {}", e))]
+}
+
+fn write_asm(filestem : &str, instructions : &Vec<Instruction>) -> Result<(), Vec<VmError>> {
     let writer = ASMWriter::new(filestem);
-    writer.write(instructions);
+    writer.write(instructions).map_err(|e| write_error(&format!("{}.asm", filestem), e))
 }
-fn write_hack(filestem : &str, instructions : &Vec<Instruction>) {
+fn write_hack(filestem : &str, instructions : &Vec<Instruction>) -> Result<(), Vec<VmError>> {
     let mut assembler = Assembler::new();
     let assembly = assembler.assemble(&instructions);
     let assembly_writer = AssemblyWriter::new(filestem);
-    assembly_writer.write(assembly);
+    assembly_writer.write(assembly).map_err(|e| write_error(&format!("{}.hack", filestem), e))
 }
 
-fn generate_asm(compiler: &mut Compiler, filename : &str) -> Vec<Instruction> {
-    let parser = VMInstructionParser::new(filename);
-    let vm_instructions = parser.parse().unwrap();
-    compiler.compile(vm_instructions)    
+fn vm_files(path : &Path) -> Result<Vec<PathBuf>, Vec<VmError>> {
+    if path.is_file() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    // get all .vm files in that dir, sorted so the output doesn't depend on read_dir order
+    println!("Processing directory {:?}", path);
+    let read_error = |e : std::io::Error| vec![VmError::new(&path.to_string_lossy(), 0, "", &format!("unable to read directory: {}", e))];
+    let mut files = Vec::new();
+    for dir_entry in std::fs::read_dir(path).map_err(read_error)? {
+        let file_path = dir_entry.map_err(read_error)?.path();
+        if file_path.extension() == Some(OsStr::new("vm")) {
+            files.push(file_path);
+        }
+    }
+    files.sort();
+    Ok(files)
 }
 
-fn compile_dir(mut compiler: &mut Compiler, path : &Path) -> Vec<Instruction> {
-    // get all .vm files in that dir
-    println!("Processing directory {:?}", path);
-    let dir_entries = std::fs::read_dir(path).expect(format!("File {:?} not found", path).as_str());    
-    dir_entries.into_iter().filter(|f| {
-        f.as_ref().unwrap().path().as_path().extension().unwrap() == "vm"
-    })
-    .map(|dir_entry| {
-        generate_asm(&mut compiler, dir_entry.unwrap().path().to_str().unwrap())
-    })
-    .flatten()
-    .collect()
-}
-
-fn compile(path : &Path, target : &str) {
+fn parse_files(files : &[PathBuf]) -> Result<Vec<(String, Vec<VMInstruction>)>, Vec<VmError>> {
+    let mut programs = Vec::new();
+    let mut errors = Vec::new();
+    for file_path in files {
+        let filename = match path_str(file_path) {
+            Ok(filename) => filename,
+            Err(mut path_errors) => {
+                errors.append(&mut path_errors);
+                continue;
+            }
+        };
+        match VMInstructionParser::new(filename).parse() {
+            Ok(vm_instructions) => programs.push((filename.to_string(), vm_instructions)),
+            Err(mut file_errors) => errors.append(&mut file_errors)
+        }
+    }
+    if errors.is_empty() {
+        Ok(programs)
+    } else {
+        Err(errors)
+    }
+}
+
+fn compile_files(compiler: &mut Compiler, programs : &[(String, Vec<VMInstruction>)]) -> Result<Vec<Instruction>, Vec<VmError>> {
+    let mut instructions = Vec::new();
+    let mut errors = Vec::new();
+    for (filename, vm_instructions) in programs {
+        match compiler.compile(filename, vm_instructions.clone()) {
+            Ok(mut compiled) => instructions.append(&mut compiled),
+            Err(mut file_errors) => errors.append(&mut file_errors)
+        }
+    }
+    if errors.is_empty() {
+        Ok(instructions)
+    } else {
+        Err(errors)
+    }
+}
+
+// number of ROM words the instructions assemble to
+fn rom_size(instructions : &[Instruction]) -> usize {
+    instructions.iter().filter(|ins| matches!(ins, Instruction::AInstruction { .. } | Instruction::CInstruction { .. })).count()
+}
+
+fn translate(programs : &[(String, Vec<VMInstruction>)], options : &Options, shared_routines : bool) -> Result<Vec<Instruction>, Vec<VmError>> {
     let mut compiler = Compiler::new();
-    let mut instructions = compiler.generate_bootstrap();
-    let mut compiled_instructions = compile_dir(&mut compiler, &path);
+    compiler.set_shared_routines(shared_routines);
+    compiler.set_optimizer(Optimizer::new(&options.passes));
+    let mut instructions = if options.bootstrap { compiler.generate_bootstrap() } else { Vec::new() };
+    let mut compiled_instructions = compile_files(&mut compiler, programs)?;
     instructions.append(&mut compiled_instructions);
-    let mut target_file_stem = path.to_path_buf();
-    target_file_stem.push(target);               
-    write_asm(target_file_stem.to_str().unwrap(), &instructions);
-    write_hack(target_file_stem.to_str().unwrap(), &instructions);
+    instructions.append(&mut compiler.generate_runtime());
+    Ok(instructions)
+}
+
+fn defines(programs : &[(String, Vec<VMInstruction>)], function : &str) -> bool {
+    programs.iter().flat_map(|(_, vm_instructions)| vm_instructions)
+        .any(|ins| matches!(ins, VMInstruction::CFunction { symbol, .. } if symbol == function))
+}
+
+// Sys.init may come from the OS at run time (projects/11 only ships the program's
+// own files), but dead code elimination needs it here to know what the bootstrap reaches
+fn check_bootstrap(programs : &[(String, Vec<VMInstruction>)], options : &Options) -> Result<(), Vec<VmError>> {
+    if defines(programs, "Sys.init") {
+        Ok(())
+    } else {
+        let input = options.input.to_string_lossy();
+        Err(vec![VmError::new(&input, 0, "", "Sys.init is not defined but the bootstrap calls it, use --no-bootstrap to eliminate dead code without it")])
+    }
+}
+
+fn compile(options : &Options) -> Result<(), Vec<VmError>> {
+    let mut programs = parse_files(&vm_files(&options.input)?)?;
+    if options.bootstrap && options.eliminate_dead_code {
+        check_bootstrap(&programs, options)?;
+    }
+    if let Some(max_size) = options.inline_size {
+        let bootstrapped = options.bootstrap && defines(&programs, "Sys.init");
+        let (filenames, files) : (Vec<String>, Vec<Vec<VMInstruction>>) = programs.into_iter().unzip();
+        let mut inliner = Inliner::new(max_size);
+        // without Sys.init the callers may be outside these files, so assume any function can run
+        if bootstrapped {
+            inliner.add_entry_point("Sys.init");
+        }
+        let (inlined_files, inlined) = inliner.inline(files);
+        for function in inlined {
+            println!("Inlined calls to {}", function);
+        }
+        programs = filenames.into_iter().zip(inlined_files).collect();
+    }
+    if options.eliminate_dead_code {
+        let (filenames, files) : (Vec<String>, Vec<Vec<VMInstruction>>) = programs.into_iter().unzip();
+        let mut eliminator = DeadFunctionEliminator::new(&options.entry_point);
+        // the bootstrap calls Sys.init whatever the entry point
+        if options.bootstrap {
+            eliminator.add_entry_point("Sys.init");
+        }
+        let (kept, removed) = eliminator.eliminate(files);
+        for function in removed {
+            println!("Removed unreachable function {}", function);
+        }
+        programs = filenames.into_iter().zip(kept).collect();
+    }
+    let instructions = translate(&programs, options, options.shared_routines)?;
+    if options.shared_routines {
+        let inlined_size = rom_size(&translate(&programs, options, false)?);
+        println!("ROM size {} words, {} with inlined routines", rom_size(&instructions), inlined_size);
+    }
+    let target_file_stem = options.output_stem();
+    let target_file_stem = path_str(&target_file_stem)?;
+    if options.emit != Emit::Hack {
+        write_asm(target_file_stem, &instructions)?;
+    }
+    if options.emit != Emit::Asm {
+        write_hack(target_file_stem, &instructions)?;
+    }
+    Ok(())
 }
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    let mut path = Path::new(args[1].as_str());
-    let target = args[2].as_str();
-    if path.is_file(){
-        path = path.parent().unwrap();
-    }
-    compile(path, target);
-}
\ No newline at end of file
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match Options::parse(&args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n{}", message, USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(errors) = compile(&options) {
+        eprintln!("Translation failed with {} error(s):", errors.len());
+        for error in errors {
+            eprintln!("  {}", error);
+        }
+        process::exit(1);
+    }
+}
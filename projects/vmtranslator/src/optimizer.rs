@@ -0,0 +1,489 @@
+use crate::{VMInstruction, TranslatorInstruction, Segment, PopSegment, ArithmeticOp};
+use std::str::FromStr;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pass {
+    // push constant 2; push constant 3; add => push constant 5
+    FoldConstants,
+    // not; if-goto L => one jump on the inverted condition
+    InvertBranches,
+    // push local 0; pop static 1 => one copy that skips the stack
+    FuseMoves
+}
+
+impl FromStr for Pass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Pass, String> {
+        match s {
+            "fold-constants" => Ok(Self::FoldConstants),
+            "invert-branches" => Ok(Self::InvertBranches),
+            "fuse-moves" => Ok(Self::FuseMoves),
+            _ => Err(format!("Unknown optimization pass {}", s))
+        }
+    }
+}
+
+pub struct Optimizer {
+    passes : Vec<Pass>
+}
+
+impl Optimizer {
+    // passes always run in this order, so folded constants can then be fused into moves
+    pub const ALL : &'static [Pass] = &[Pass::FoldConstants, Pass::InvertBranches, Pass::FuseMoves];
+
+    pub fn new(passes : &[Pass]) -> Optimizer {
+        Optimizer { passes: passes.to_vec() }
+    }
+
+    // folding stays within the VM language, the later passes fuse instructions
+    // into forms only the compiler understands
+    pub(crate) fn optimize(&self, vm_instructions : Vec<VMInstruction>) -> Vec<TranslatorInstruction> {
+        let mut vm_instructions = vm_instructions;
+        if self.passes.contains(&Pass::FoldConstants) {
+            vm_instructions = Optimizer::fold_constants(vm_instructions);
+        }
+        let mut output = vm_instructions.into_iter().map(TranslatorInstruction::Vm).collect();
+        if self.passes.contains(&Pass::InvertBranches) {
+            output = Optimizer::invert_branches(output);
+        }
+        if self.passes.contains(&Pass::FuseMoves) {
+            output = Optimizer::fuse_moves(output);
+        }
+        output
+    }
+
+    fn fold_constants(vm_instructions : Vec<VMInstruction>) -> Vec<VMInstruction> {
+        let mut output = Vec::new();
+        for ins in vm_instructions {
+            output.push(ins);
+            // keep folding so chains like 1 2 add 3 add collapse fully
+            while Optimizer::fold_tail(&mut output) {}
+        }
+        output
+    }
+
+    fn fold_tail(output : &mut Vec<VMInstruction>) -> bool {
+        let folded = match output.as_slice() {
+            [.., VMInstruction::CPush { segment: Segment::Constant, value: x }, VMInstruction::CPush { segment: Segment::Constant, value: y }, VMInstruction::CArithmetic { cmd }] => {
+                Optimizer::binary(*cmd, *x as u16, *y as u16).map(|value| (3, value))
+            },
+            _ => None
+        }.or_else(|| match output.as_slice() {
+            [.., VMInstruction::CPush { segment: Segment::Constant, value: x }, VMInstruction::CArithmetic { cmd }] => {
+                Optimizer::unary(*cmd, *x as u16).map(|value| (2, value))
+            },
+            _ => None
+        });
+        match folded {
+            Some((count, value)) => {
+                output.truncate(output.len() - count);
+                output.push(VMInstruction::CPush { segment: Segment::Constant, value: value as u32 });
+                true
+            },
+            None => false
+        }
+    }
+
+    // evaluated as the Hack ALU would, so comparisons keep the overflow behaviour of x - y
+    fn binary(cmd : ArithmeticOp, x : u16, y : u16) -> Option<u16> {
+        let to_bool = |b : bool| if b { 0xFFFF } else { 0 };
+        match cmd {
+            ArithmeticOp::Add => Some(x.wrapping_add(y)),
+            ArithmeticOp::Sub => Some(x.wrapping_sub(y)),
+            ArithmeticOp::And => Some(x & y),
+            ArithmeticOp::Or => Some(x | y),
+            ArithmeticOp::Eq => Some(to_bool(x == y)),
+            ArithmeticOp::Gt => Some(to_bool((x.wrapping_sub(y) as i16) > 0)),
+            ArithmeticOp::Lt => Some(to_bool((x.wrapping_sub(y) as i16) < 0)),
+            ArithmeticOp::Neg | ArithmeticOp::Not => None
+        }
+    }
+
+    fn unary(cmd : ArithmeticOp, x : u16) -> Option<u16> {
+        match cmd {
+            ArithmeticOp::Neg => Some(x.wrapping_neg()),
+            ArithmeticOp::Not => Some(!x),
+            _ => None
+        }
+    }
+
+    fn invert_branches(instructions : Vec<TranslatorInstruction>) -> Vec<TranslatorInstruction> {
+        let mut output : Vec<TranslatorInstruction> = Vec::new();
+        for ins in instructions {
+            match (output.last(), ins) {
+                (Some(TranslatorInstruction::Vm(VMInstruction::CArithmetic { cmd: ArithmeticOp::Not })), TranslatorInstruction::Vm(VMInstruction::CIf { label })) => {
+                    output.pop();
+                    output.push(TranslatorInstruction::IfNot { label });
+                },
+                (_, ins) => output.push(ins)
+            }
+        }
+        output
+    }
+
+    fn fuse_moves(instructions : Vec<TranslatorInstruction>) -> Vec<TranslatorInstruction> {
+        let mut output : Vec<TranslatorInstruction> = Vec::new();
+        for ins in instructions {
+            match (output.last(), ins) {
+                (Some(&TranslatorInstruction::Vm(VMInstruction::CPush { segment: from, value: from_value })), TranslatorInstruction::Vm(VMInstruction::CPop { segment: to, value: to_value })) => {
+                    output.pop();
+                    output.push(TranslatorInstruction::Move { from, from_value, to, to_value });
+                },
+                (_, ins) => output.push(ins)
+            }
+        }
+        output
+    }
+}
+
+// Drops functions that can't be reached through `call` from the entry points.
+// Calls are matched by name, so the analysis stays conservative: every call
+// in reachable code keeps its target, code outside any function is always
+// kept, and nothing is removed if an entry point isn't defined.
+pub struct DeadFunctionEliminator {
+    entry_points : Vec<String>
+}
+
+impl DeadFunctionEliminator {
+    pub fn new(entry_point : &str) -> DeadFunctionEliminator {
+        DeadFunctionEliminator { entry_points: vec![entry_point.to_string()] }
+    }
+
+    // also keep everything reachable from this function, e.g. Sys.init for the bootstrap
+    pub fn add_entry_point(&mut self, entry_point : &str) {
+        if !self.entry_points.iter().any(|f| f == entry_point) {
+            self.entry_points.push(entry_point.to_string());
+        }
+    }
+
+    // takes the instructions of each file, returns them without unreachable
+    // functions along with the sorted names of the functions removed
+    pub fn eliminate(&self, files : Vec<Vec<VMInstruction>>) -> (Vec<Vec<VMInstruction>>, Vec<String>) {
+        let mut calls : HashMap<&str, Vec<&str>> = HashMap::new();
+        let mut roots : Vec<&str> = self.entry_points.iter().map(|f| f.as_str()).collect();
+        for file in files.iter() {
+            let mut function : Option<&str> = None;
+            for ins in file {
+                match ins {
+                    VMInstruction::CFunction { symbol, .. } => {
+                        function = Some(symbol);
+                        calls.entry(symbol).or_default();
+                    },
+                    VMInstruction::CCall { symbol, .. } => match function {
+                        Some(caller) => calls.entry(caller).or_default().push(symbol),
+                        None => roots.push(symbol)
+                    },
+                    _ => {}
+                }
+            }
+        }
+        if !self.entry_points.iter().all(|f| calls.contains_key(f.as_str())) {
+            return (files, Vec::new());
+        }
+
+        let mut reachable = HashSet::new();
+        while let Some(function) = roots.pop() {
+            if reachable.insert(function) {
+                if let Some(callees) = calls.get(function) {
+                    roots.extend(callees);
+                }
+            }
+        }
+        let mut removed : Vec<String> = calls.keys().filter(|f| !reachable.contains(*f)).map(|f| f.to_string()).collect();
+        removed.sort();
+
+        let kept = files.iter().map(|file| {
+            let mut keep = true;
+            file.iter().filter(|ins| {
+                if let VMInstruction::CFunction { symbol, .. } = ins {
+                    keep = reachable.contains(symbol.as_str());
+                }
+                keep
+            }).cloned().collect()
+        }).collect();
+        (kept, removed)
+    }
+}
+
+// A leaf function that is small and simple enough to substitute at its call sites
+struct InlineCandidate {
+    file : usize,
+    n_vars : u32,
+    body : Vec<VMInstruction>,
+    max_argument : Option<u32>,
+    uses_static : bool,
+    writes_this : bool,
+    writes_that : bool
+}
+
+// Substitutes the body of small leaf functions at their call sites. The
+// callee's arguments, locals and any THIS / THAT it changes are held in the
+// temp segment, so a call is only inlined where none of the slots it needs
+// can be read before being written again. To keep the rewrite simple only
+// straight line callees that don't use temp themselves, and that leave
+// exactly the return value on the stack, are inlined. Callees using static
+// are only inlined within their own file.
+pub struct Inliner {
+    max_size : usize,
+    entry_points : Vec<String>
+}
+
+impl Inliner {
+    const TEMP_SLOTS : u32 = 8;
+    const ALL_TEMPS : u8 = 0xFF;
+
+    pub fn new(max_size : usize) -> Inliner {
+        Inliner { max_size, entry_points: Vec::new() }
+    }
+
+    // a function run by the bootstrap, e.g. Sys.init. Nothing reads temp after
+    // it returns, and functions nobody calls are taken to never run.
+    pub fn add_entry_point(&mut self, entry_point : &str) {
+        self.entry_points.push(entry_point.to_string());
+    }
+
+    // takes the instructions of each file, returns them with the calls
+    // inlined along with the sorted names of the functions that were inlined
+    pub fn inline(&self, files : Vec<Vec<VMInstruction>>) -> (Vec<Vec<VMInstruction>>, Vec<String>) {
+        let mut candidates = HashMap::new();
+        for (file, vm_instructions) in files.iter().enumerate() {
+            let mut index = 0;
+            while index < vm_instructions.len() {
+                if let VMInstruction::CFunction { symbol, n_vars } = &vm_instructions[index] {
+                    let end = vm_instructions[index + 1..].iter()
+                        .position(|ins| matches!(ins, VMInstruction::CFunction { .. }))
+                        .map_or(vm_instructions.len(), |offset| index + 1 + offset);
+                    if let Some(candidate) = self.candidate(file, *n_vars, &vm_instructions[index + 1..end]) {
+                        candidates.insert(symbol.clone(), candidate);
+                    }
+                    index = end;
+                } else {
+                    index += 1;
+                }
+            }
+        }
+
+        let live = self.live_temps(&files);
+        let mut inlined = HashSet::new();
+        let output = files.into_iter().enumerate().map(|(file, vm_instructions)| {
+            let mut output = Vec::new();
+            for (index, ins) in vm_instructions.into_iter().enumerate() {
+                match &ins {
+                    VMInstruction::CCall { symbol, n_args } => match candidates.get(symbol) {
+                        Some(candidate) if Inliner::fits(candidate, file, *n_args, live[file][index]) => {
+                            Inliner::expand(candidate, *n_args, &mut output);
+                            inlined.insert(symbol.clone());
+                        },
+                        _ => output.push(ins)
+                    },
+                    _ => output.push(ins)
+                }
+            }
+            output
+        }).collect();
+        let mut inlined : Vec<String> = inlined.into_iter().collect();
+        inlined.sort();
+        (output, inlined)
+    }
+
+    fn candidate(&self, file : usize, n_vars : u32, body : &[VMInstruction]) -> Option<InlineCandidate> {
+        let (last, body) = body.split_last()?;
+        if !matches!(last, VMInstruction::CReturn) || body.len() > self.max_size {
+            return None;
+        }
+        let mut candidate = InlineCandidate {
+            file, n_vars, body: body.to_vec(), max_argument: None,
+            uses_static: false, writes_this: false, writes_that: false
+        };
+        // track the stack depth, the return value must be the only thing left on it
+        let mut depth : i32 = 0;
+        for ins in body {
+            let (reads, writes, effect) = match ins {
+                VMInstruction::CPush { segment, value } => (Some((*segment, *value)), None, 1),
+                VMInstruction::CPop { segment, value } => (None, Some((*segment, *value)), -1),
+                VMInstruction::CArithmetic { cmd: ArithmeticOp::Neg | ArithmeticOp::Not } => (None, None, 0),
+                VMInstruction::CArithmetic { .. } => (None, None, -1),
+                _ => return None
+            };
+            for (segment, value) in reads.into_iter().chain(writes.map(|(segment, value)| (segment.into(), value))) {
+                match segment {
+                    Segment::Temp => return None,
+                    Segment::Static => candidate.uses_static = true,
+                    Segment::Argument => candidate.max_argument = candidate.max_argument.max(Some(value)),
+                    _ => {}
+                }
+            }
+            match (reads, writes) {
+                (_, Some((PopSegment::Pointer, 0))) => candidate.writes_this = true,
+                (_, Some((PopSegment::Pointer, 1))) => candidate.writes_that = true,
+                // pointer 2 and above land in the temp segment
+                (Some((Segment::Pointer, 2..)), _) | (_, Some((PopSegment::Pointer, 2..))) => return None,
+                _ => {}
+            }
+            depth += effect;
+            if depth < 0 {
+                return None;
+            }
+        }
+        if depth == 1 { Some(candidate) } else { None }
+    }
+
+    // live_after is the set of temp slots the caller may still read after the call
+    fn fits(candidate : &InlineCandidate, file : usize, n_args : u32, live_after : u8) -> bool {
+        let slots = n_args + candidate.n_vars + candidate.writes_this as u32 + candidate.writes_that as u32;
+        let clobbered = ((1u16 << slots.min(Inliner::TEMP_SLOTS)) - 1) as u8;
+        (!candidate.uses_static || candidate.file == file)
+            && candidate.max_argument.is_none_or(|max| max < n_args)
+            && slots <= Inliner::TEMP_SLOTS
+            && live_after & clobbered == 0
+    }
+
+    // The temp slots left live after each instruction of each file, as a bit
+    // per slot. A call reads whatever the callee may read before writing, and a
+    // return leaves live whatever its callers may read after the call. Calls to
+    // unknown functions and code that runs off the end of a file keep every slot
+    // live, as do returns from functions nobody calls unless there are entry
+    // points, since without them those may be run from outside the program.
+    fn live_temps(&self, files : &[Vec<VMInstruction>]) -> Vec<Vec<u8>> {
+        // indices past temp 7 don't name a slot, reading them keeps every slot live
+        let temp = |value : u32| (value < Inliner::TEMP_SLOTS).then(|| 1u8 << value);
+        // (file, start, end) of the top level code and of each function
+        let mut scopes = Vec::new();
+        let mut called = HashSet::new();
+        for (file, vm_instructions) in files.iter().enumerate() {
+            let mut start = 0;
+            for (index, ins) in vm_instructions.iter().enumerate() {
+                match ins {
+                    VMInstruction::CFunction { .. } if index > start => {
+                        scopes.push((file, start, index));
+                        start = index;
+                    },
+                    VMInstruction::CCall { symbol, .. } => {
+                        called.insert(symbol.as_str());
+                    },
+                    _ => {}
+                }
+            }
+            scopes.push((file, start, vm_instructions.len()));
+        }
+        // slots each function may read before writing, and that its callers may read after it returns
+        let mut reads : HashMap<&str, u8> = HashMap::new();
+        let mut returns : HashMap<&str, u8> = HashMap::new();
+        for vm_instructions in files {
+            for ins in vm_instructions {
+                if let VMInstruction::CFunction { symbol, .. } = ins {
+                    reads.insert(symbol, 0);
+                    let outside_caller = self.entry_points.is_empty() && !called.contains(symbol.as_str());
+                    returns.insert(symbol, if outside_caller { Inliner::ALL_TEMPS } else { 0 });
+                }
+            }
+        }
+
+        let mut live_in : Vec<Vec<u8>> = files.iter().map(|f| vec![0; f.len()]).collect();
+        let mut live_out = live_in.clone();
+        // the sets only grow, so this settles
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &(file, start, end) in scopes.iter() {
+                let vm_instructions = &files[file];
+                let function = match vm_instructions.get(start) {
+                    Some(VMInstruction::CFunction { symbol, .. }) => Some(symbol.as_str()),
+                    _ => None
+                };
+                let labels : HashMap<&str, usize> = (start..end).filter_map(|index| match &vm_instructions[index] {
+                    VMInstruction::CLabel { label } => Some((label.as_str(), index)),
+                    _ => None
+                }).collect();
+                let target = |label : &String, live_in : &[u8]| labels.get(label.as_str()).map_or(Inliner::ALL_TEMPS, |&index| live_in[index]);
+                for index in (start..end).rev() {
+                    let next = if index + 1 < end { live_in[file][index + 1] } else { Inliner::ALL_TEMPS };
+                    let out = match &vm_instructions[index] {
+                        VMInstruction::CGoto { label } => target(label, &live_in[file]),
+                        VMInstruction::CIf { label } => next | target(label, &live_in[file]),
+                        VMInstruction::CReturn => function.map_or(Inliner::ALL_TEMPS, |f| returns[f]),
+                        _ => next
+                    };
+                    let live = match &vm_instructions[index] {
+                        VMInstruction::CPush { segment: Segment::Temp, value } => out | temp(*value).unwrap_or(Inliner::ALL_TEMPS),
+                        VMInstruction::CPop { segment: PopSegment::Temp, value } => out & !temp(*value).unwrap_or(0),
+                        // pointer 2 and above alias the temp segment
+                        VMInstruction::CPush { segment: Segment::Pointer, value: 2.. } => Inliner::ALL_TEMPS,
+                        VMInstruction::CCall { symbol, .. } => out | reads.get(symbol.as_str()).copied().unwrap_or(Inliner::ALL_TEMPS),
+                        _ => out
+                    };
+                    if out != live_out[file][index] || live != live_in[file][index] {
+                        live_out[file][index] = out;
+                        live_in[file][index] = live;
+                        changed = true;
+                    }
+                }
+                if let Some(function) = function {
+                    reads.insert(function, live_in[file][start]);
+                }
+                for index in start..end {
+                    if let VMInstruction::CCall { symbol, .. } = &vm_instructions[index] {
+                        if let Some(after) = returns.get_mut(symbol.as_str()) {
+                            if *after | live_out[file][index] != *after {
+                                *after |= live_out[file][index];
+                                changed = true;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        live_out
+    }
+
+    fn expand(candidate : &InlineCandidate, n_args : u32, output : &mut Vec<VMInstruction>) {
+        // arguments are on the stack, last one on top
+        for i in (0..n_args).rev() {
+            output.push(VMInstruction::CPop { segment: PopSegment::Temp, value: i });
+        }
+        for j in 0..candidate.n_vars {
+            output.push(VMInstruction::CPush { segment: Segment::Constant, value: 0 });
+            output.push(VMInstruction::CPop { segment: PopSegment::Temp, value: n_args + j });
+        }
+        let mut saved = Vec::new();
+        let mut slot = n_args + candidate.n_vars;
+        for (pointer, writes) in [(0, candidate.writes_this), (1, candidate.writes_that)] {
+            if writes {
+                output.push(VMInstruction::CPush { segment: Segment::Pointer, value: pointer });
+                output.push(VMInstruction::CPop { segment: PopSegment::Temp, value: slot });
+                saved.push((pointer, slot));
+                slot += 1;
+            }
+        }
+        let rewrite = |segment : Segment, value : u32| match segment {
+            Segment::Argument => (Segment::Temp, value),
+            Segment::Local => (Segment::Temp, n_args + value),
+            _ => (segment, value)
+        };
+        let rewrite_pop = |segment : PopSegment, value : u32| match segment {
+            PopSegment::Argument => (PopSegment::Temp, value),
+            PopSegment::Local => (PopSegment::Temp, n_args + value),
+            _ => (segment, value)
+        };
+        for ins in candidate.body.iter() {
+            output.push(match *ins {
+                VMInstruction::CPush { segment, value } => {
+                    let (segment, value) = rewrite(segment, value);
+                    VMInstruction::CPush { segment, value }
+                },
+                VMInstruction::CPop { segment, value } => {
+                    let (segment, value) = rewrite_pop(segment, value);
+                    VMInstruction::CPop { segment, value }
+                },
+                ref other => other.clone()
+            });
+        }
+        // restore the caller's THIS / THAT, leaving the return value on top
+        for (pointer, slot) in saved {
+            output.push(VMInstruction::CPush { segment: Segment::Temp, value: slot });
+            output.push(VMInstruction::CPop { segment: PopSegment::Pointer, value: pointer });
+        }
+    }
+}
@@ -0,0 +1,38 @@
+pub const SP : usize = 0;
+pub const RAM_SIZE : usize = 24577;
+
+// Minimal Hack CPU, enough to run translated test programs until they fall off
+// the end of ROM or reach a jump to itself (label HALT; goto HALT)
+pub fn run(rom : &[u32], ram : &mut [u16]) {
+    let (mut a, mut d, mut pc) : (u16, u16, usize) = (0, 0, 0);
+    let mut cycles = 0;
+    while pc < rom.len() {
+        cycles += 1;
+        assert!(cycles < 1_000_000, "program did not terminate");
+        let ins = rom[pc];
+        if ins & 0x8000 == 0 {
+            a = ins as u16;
+            pc += 1;
+            continue;
+        }
+        let bit = |n : u32| ins & (1 << n) != 0;
+        let mut x = d;
+        let mut y = if bit(12) { ram[a as usize] } else { a };
+        if bit(11) { x = 0 }
+        if bit(10) { x = !x }
+        if bit(9) { y = 0 }
+        if bit(8) { y = !y }
+        let mut out = if bit(7) { x.wrapping_add(y) } else { x & y };
+        if bit(6) { out = !out }
+        if bit(3) { ram[a as usize] = out }
+        if bit(4) { d = out }
+        let address = a;
+        if bit(5) { a = out }
+        let signed = out as i16;
+        let jump = (bit(2) && signed < 0) || (bit(1) && signed == 0) || (bit(0) && signed > 0);
+        if jump && address as usize + 1 == pc {
+            break;
+        }
+        pc = if jump { address as usize } else { pc + 1 };
+    }
+}
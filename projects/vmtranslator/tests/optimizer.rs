@@ -0,0 +1,424 @@
+use assembler::{Assembler, Instruction};
+use vmtranslator::{Compiler, VMInstruction, VMInstructionParser};
+use vmtranslator::optimizer::{DeadFunctionEliminator, Inliner, Optimizer, Pass};
+
+mod common;
+use common::{run, RAM_SIZE, SP};
+
+fn translate(source : &str, passes : &[Pass]) -> Vec<Instruction> {
+    let vm_instructions = VMInstructionParser::new("Test.vm").parse_str(source).unwrap();
+    let mut compiler = Compiler::new();
+    compiler.set_optimizer(Optimizer::new(passes));
+    compiler.compile("Test.vm", vm_instructions).unwrap()
+}
+
+// translates the program with the given passes and runs it with the segments
+// laid out as in the projects/07 tests; returns the final RAM and ROM size
+fn execute(source : &str, passes : &[Pass]) -> (Vec<u16>, usize) {
+    let rom = Assembler::new().assemble(&translate(source, passes));
+    let mut ram = vec![0; RAM_SIZE];
+    ram[SP] = 256;
+    ram[1] = 300;
+    ram[2] = 400;
+    ram[3] = 3000;
+    ram[4] = 3010;
+    for i in 0..10 {
+        ram[400 + i] = (i * 7) as u16;
+        ram[3010 + i] = (i * 11) as u16;
+    }
+    run(&rom, &mut ram);
+    (ram, rom.len())
+}
+
+// RAM that a correct program may observe: everything except the scratch
+// registers R13-R15 and the stack slots above SP
+fn observable(ram : &[u16]) -> Vec<(usize, u16)> {
+    let sp = ram[SP] as usize;
+    ram.iter().cloned().enumerate()
+        .filter(|(address, _)| !(13..16).contains(address) && !(sp..300).contains(address))
+        .collect()
+}
+
+fn assert_unchanged(source : &str, passes : &[Pass]) {
+    let (plain, plain_size) = execute(source, &[]);
+    let (optimized, optimized_size) = execute(source, passes);
+    assert_eq!(observable(&plain), observable(&optimized));
+    assert!(optimized_size < plain_size, "expected {:?} to shrink the program, {} -> {}", passes, plain_size, optimized_size);
+}
+
+const ARITHMETIC : &str = "
+push constant 2
+push constant 3
+add
+push constant 7
+sub
+pop local 0
+push constant 1
+neg
+pop local 1
+push constant 32767
+push constant 1
+neg
+gt
+pop local 2
+push constant 5
+push constant 5
+eq
+not
+pop local 3
+push constant 12
+push constant 10
+and
+push constant 3
+or
+push constant 9
+lt
+push constant 40000
+pop local 5
+pop local 4
+push argument 1
+push constant 4
+add
+pop local 6
+";
+
+const MOVES : &str = "
+push argument 3
+pop static 0
+push constant 9
+pop local 2
+push local 2
+pop temp 3
+push temp 3
+pop that 4
+push static 0
+pop argument 0
+push constant 3020
+pop pointer 1
+push that 2
+pop this 1
+push pointer 1
+pop local 0
+";
+
+const BRANCHES : &str = "
+push constant 0
+pop local 0
+push constant 5
+pop local 1
+label LOOP
+push local 1
+push constant 0
+eq
+not
+if-goto BODY
+goto END
+label BODY
+push local 0
+push local 1
+add
+pop local 0
+push local 1
+push constant 1
+sub
+pop local 1
+goto LOOP
+label END
+push constant 3
+not
+if-goto TAKEN
+push constant 111
+pop local 2
+label TAKEN
+push constant 1
+neg
+not
+if-goto SKIPPED
+push constant 222
+pop local 3
+label SKIPPED
+";
+
+#[test]
+fn fold_constants_keeps_behaviour() {
+    assert_unchanged(ARITHMETIC, &[Pass::FoldConstants]);
+}
+
+#[test]
+fn fold_constants_collapses_to_single_push() {
+    let folded = translate("push constant 1\nneg\npush constant 2\npush constant 3\nadd\nadd", &[Pass::FoldConstants]);
+    let single = translate("push constant 4", &[]);
+    assert_eq!(Assembler::new().assemble(&folded), Assembler::new().assemble(&single));
+}
+
+#[test]
+fn fuse_moves_keeps_behaviour() {
+    assert_unchanged(MOVES, &[Pass::FuseMoves]);
+}
+
+#[test]
+fn invert_branches_keeps_behaviour() {
+    assert_unchanged(BRANCHES, &[Pass::InvertBranches]);
+}
+
+#[test]
+fn all_passes_keep_behaviour() {
+    for source in [ARITHMETIC, MOVES, BRANCHES] {
+        assert_unchanged(source, Optimizer::ALL);
+    }
+}
+
+// comments that parse as VM code; the compiler also adds its own notes
+fn vm_comments(instructions : Vec<Instruction>) -> Vec<String> {
+    instructions.into_iter().filter_map(|ins| match ins {
+        Instruction::Comment { contents } if VMInstructionParser::new("Test.vm").parse_str(&contents).is_ok() => Some(contents),
+        _ => None
+    }).collect()
+}
+
+// fused instructions are commented with the VM code they replace
+#[test]
+fn fused_instructions_keep_their_vm_comments() {
+    for source in [MOVES, BRANCHES] {
+        let plain = vm_comments(translate(source, &[]));
+        let fused = vm_comments(translate(source, &[Pass::InvertBranches, Pass::FuseMoves]));
+        assert_eq!(plain, fused);
+    }
+}
+
+#[test]
+fn unknown_pass_is_rejected() {
+    assert!("fold-constants".parse::<Pass>().is_ok());
+    assert!("inline-everything".parse::<Pass>().is_err());
+}
+
+
+const SYS : &str = "
+function Sys.init 0
+call Main.main 0
+label HALT
+goto HALT
+";
+
+const MAIN : &str = "
+function Main.main 0
+call Main.used 0
+return
+function Main.used 0
+push constant 1
+return
+function Main.unused 0
+call Main.alsoUnused 0
+return
+function Main.alsoUnused 0
+push constant 0
+return
+";
+
+fn function_names(files : &[Vec<VMInstruction>]) -> Vec<String> {
+    files.iter().flatten().filter_map(|ins| match ins {
+        VMInstruction::CFunction { symbol, .. } => Some(symbol.clone()),
+        _ => None
+    }).collect()
+}
+
+#[test]
+fn dead_functions_are_removed() {
+    let files = vec![
+        VMInstructionParser::new("Sys.vm").parse_str(SYS).unwrap(),
+        VMInstructionParser::new("Main.vm").parse_str(MAIN).unwrap()
+    ];
+    let (kept, removed) = DeadFunctionEliminator::new("Sys.init").eliminate(files);
+    assert_eq!(function_names(&kept), vec!["Sys.init", "Main.main", "Main.used"]);
+    assert_eq!(removed, vec!["Main.alsoUnused", "Main.unused"]);
+}
+
+#[test]
+fn bootstrap_entry_point_is_kept() {
+    let files = vec![
+        VMInstructionParser::new("Sys.vm").parse_str(SYS).unwrap(),
+        VMInstructionParser::new("Main.vm").parse_str(MAIN).unwrap()
+    ];
+    let mut eliminator = DeadFunctionEliminator::new("Main.used");
+    eliminator.add_entry_point("Sys.init");
+    let (kept, removed) = eliminator.eliminate(files);
+    assert_eq!(function_names(&kept), vec!["Sys.init", "Main.main", "Main.used"]);
+    assert_eq!(removed, vec!["Main.alsoUnused", "Main.unused"]);
+}
+
+#[test]
+fn nothing_is_removed_without_the_entry_point() {
+    let files = vec![VMInstructionParser::new("Main.vm").parse_str(MAIN).unwrap()];
+    let (kept, removed) = DeadFunctionEliminator::new("Sys.init").eliminate(files);
+    assert_eq!(function_names(&kept).len(), 4);
+    assert!(removed.is_empty());
+}
+
+const CALLER : &str = "
+push constant 3
+push constant 4
+call Test.add 2
+pop static 0
+push constant 3020
+pop pointer 0
+push constant 3000
+call Test.getX 1
+pop static 1
+push this 2
+pop static 2
+push constant 5
+call Test.square 1
+pop static 3
+push constant 2
+call Test.addStatic 1
+pop static 4
+push constant 1
+call Test.loop 1
+pop static 5
+label HALT
+goto HALT
+function Test.add 0
+push argument 0
+push argument 1
+add
+return
+function Test.getX 0
+push argument 0
+pop pointer 0
+push this 1
+return
+function Test.square 1
+push argument 0
+pop local 0
+push local 0
+push local 0
+add
+return
+function Test.addStatic 0
+push static 0
+push argument 0
+add
+return
+function Test.loop 0
+label LOOP
+push argument 0
+return
+";
+
+// translates and runs a program with functions; returns SP, the statics and the ROM size
+fn execute_inlined(source : &str, max_size : Option<usize>) -> (Vec<u16>, usize) {
+    let mut files = vec![VMInstructionParser::new("Test.vm").parse_str(source).unwrap()];
+    if let Some(max_size) = max_size {
+        files = Inliner::new(max_size).inline(files).0;
+    }
+    let instructions = Compiler::new().compile("Test.vm", files.remove(0)).unwrap();
+    let rom = Assembler::new().assemble(&instructions);
+    let mut ram = vec![0; RAM_SIZE];
+    ram[SP] = 256;
+    ram[3] = 3000;
+    for i in 0..10 {
+        ram[3000 + i] = (i * 7) as u16;
+        ram[3020 + i] = (i * 11) as u16;
+    }
+    run(&rom, &mut ram);
+    let mut observed = vec![ram[SP], ram[3]];
+    observed.extend_from_slice(&ram[16..22]);
+    (observed, rom.len())
+}
+
+#[test]
+fn inlining_keeps_behaviour() {
+    let (plain, _) = execute_inlined(CALLER, None);
+    let (inlined, _) = execute_inlined(CALLER, Some(8));
+    assert_eq!(plain, inlined);
+    assert_eq!(plain, vec![256, 3020, 7, 7, 22, 10, 9, 1]);
+}
+
+#[test]
+fn only_small_straight_line_leaves_are_inlined() {
+    let files = vec![VMInstructionParser::new("Test.vm").parse_str(CALLER).unwrap()];
+    let (_, inlined) = Inliner::new(4).inline(files);
+    assert_eq!(inlined, vec!["Test.add", "Test.addStatic", "Test.getX"]);
+}
+
+const LIVE_TEMP : &str = "
+push constant 42
+pop temp 0
+push constant 1
+call Test.id 1
+pop static 0
+push temp 0
+pop static 1
+push constant 2
+call Test.id 1
+pop static 2
+label HALT
+goto HALT
+function Test.id 0
+push argument 0
+return
+";
+
+#[test]
+fn live_temp_slots_survive_the_call() {
+    let (plain, _) = execute_inlined(LIVE_TEMP, None);
+    let (inlined, _) = execute_inlined(LIVE_TEMP, Some(8));
+    assert_eq!(plain, inlined);
+    assert_eq!(plain[2..5], [1, 42, 2]);
+    // only the second call, after the last read of temp 0, is inlined
+    let files = vec![VMInstructionParser::new("Test.vm").parse_str(LIVE_TEMP).unwrap()];
+    let (output, inlined) = Inliner::new(8).inline(files);
+    let calls = output[0].iter().filter(|ins| matches!(ins, VMInstruction::CCall { .. })).count();
+    assert_eq!((calls, inlined), (1, vec!["Test.id".to_string()]));
+}
+
+// pointer 2 aliases temp 0 and pop temp 9 writes R14, neither may be mistaken for another slot
+const ALIASED_TEMP : &str = "
+push constant 42
+pop pointer 2
+push constant 1
+call Test.id 1
+pop static 0
+push pointer 2
+pop static 1
+push constant 7
+pop temp 7
+push constant 2
+call Test.wide 1
+pop static 2
+push constant 3
+pop temp 9
+push temp 7
+pop static 3
+label HALT
+goto HALT
+function Test.id 0
+push argument 0
+return
+function Test.wide 7
+push argument 0
+return
+";
+
+#[test]
+fn out_of_range_temp_and_pointer_indices_block_inlining() {
+    let (plain, _) = execute_inlined(ALIASED_TEMP, None);
+    let (inlined, _) = execute_inlined(ALIASED_TEMP, Some(8));
+    assert_eq!(plain, inlined);
+    assert_eq!(plain[2..6], [1, 42, 2, 7]);
+    let files = vec![VMInstructionParser::new("Test.vm").parse_str(ALIASED_TEMP).unwrap()];
+    assert!(Inliner::new(8).inline(files).1.is_empty());
+    // a callee reading pointer 2 would see its own argument slot
+    let files = vec![VMInstructionParser::new("Test.vm").parse_str("push constant 1\ncall Test.alias 1\npop static 0\nlabel HALT\ngoto HALT\nfunction Test.alias 0\npush pointer 2\nreturn").unwrap()];
+    assert!(Inliner::new(8).inline(files).1.is_empty());
+}
+
+#[test]
+fn static_callees_stay_in_their_file() {
+    let files = vec![
+        VMInstructionParser::new("Main.vm").parse_str("push constant 1\ncall Test.addStatic 1\npop static 0").unwrap(),
+        VMInstructionParser::new("Test.vm").parse_str(CALLER).unwrap()
+    ];
+    let (output, _) = Inliner::new(8).inline(files);
+    assert!(matches!(output[0][1], VMInstruction::CCall { .. }));
+}
@@ -0,0 +1,84 @@
+use vmtranslator::{PopSegment, Segment, VMInstruction, VMInstructionParser};
+
+// one instruction per line, with tabs, repeated spaces and comments to skip
+const LINES : [&str; 7] = [
+    "// Test.vm",
+    "push constant 7",
+    "\tpush  local\t2   // trailing comment",
+    "",
+    "add",
+    "label LOOP",
+    "pop static 1",
+];
+
+const EXPECTED : [&str; 5] = ["push constant 7", "push local 2", "add", "label LOOP", "pop static 1"];
+
+fn text(vm_instructions : &[VMInstruction]) -> Vec<String> {
+    vm_instructions.iter().map(|ins| ins.to_string()).collect()
+}
+
+// parses the program with the bad line appended, joined with the given line ending,
+// and returns the line numbers of the reported errors
+fn error_lines(line_ending : &str, bad_line : &str) -> Vec<usize> {
+    let mut lines = LINES.to_vec();
+    lines.push(bad_line);
+    let errors = VMInstructionParser::new("Test.vm").parse_str(&lines.join(line_ending)).unwrap_err();
+    errors.iter().map(|error| error.line).collect()
+}
+
+#[test]
+fn every_line_ending_parses_the_same() {
+    for line_ending in ["\n", "\r\n", "\r"] {
+        let vm_instructions = VMInstructionParser::new("Test.vm").parse_str(&LINES.join(line_ending)).unwrap();
+        assert_eq!(text(&vm_instructions), EXPECTED, "line ending {:?}", line_ending);
+    }
+}
+
+#[test]
+fn mixed_line_endings_parse_the_same() {
+    let source = format!("{}\n{}\r\n{}\r{}\n{}\r\n{}\r{}", LINES[0], LINES[1], LINES[2], LINES[3], LINES[4], LINES[5], LINES[6]);
+    let vm_instructions = VMInstructionParser::new("Test.vm").parse_str(&source).unwrap();
+    assert_eq!(text(&vm_instructions), EXPECTED);
+}
+
+#[test]
+fn errors_report_the_same_line_for_every_line_ending() {
+    for line_ending in ["\n", "\r\n", "\r"] {
+        assert_eq!(error_lines(line_ending, "pop constant 3"), vec![8], "line ending {:?}", line_ending);
+    }
+}
+
+#[test]
+fn errors_report_line_and_text() {
+    let source = "push constant 1\r\n\r\n\tpush nowhere 2 // bad segment\r\nadd\r\nfrobnicate\r\n";
+    let errors = VMInstructionParser::new("Test.vm").parse_str(source).unwrap_err();
+    let reported : Vec<(usize, &str)> = errors.iter().map(|error| (error.line, error.text.as_str())).collect();
+    assert_eq!(reported, vec![(3, "push nowhere 2 // bad segment"), (5, "frobnicate")]);
+    assert_eq!(errors[1].to_string(), "Test.vm:5: Unexpected instruction - `frobnicate`");
+}
+
+#[test]
+fn reader_parses_like_str() {
+    let source = LINES.join("\r\n");
+    let vm_instructions = VMInstructionParser::new("Test.vm").parse_reader(source.as_bytes()).unwrap();
+    assert_eq!(text(&vm_instructions), EXPECTED);
+}
+
+#[test]
+fn constant_is_not_a_pop_segment() {
+    assert!("constant".parse::<PopSegment>().is_err());
+    assert!(PopSegment::try_from(Segment::Constant).is_err());
+    assert_eq!("pointer".parse::<PopSegment>(), Ok(PopSegment::Pointer));
+    assert_eq!(Segment::from(PopSegment::Temp), Segment::Temp);
+}
+
+#[test]
+fn undefined_labels_report_line_and_text() {
+    let source = "function Foo.bar 0\nlabel A\n\n  goto B // nowhere\nreturn\nfunction Foo.baz 0\r\nif-goto A\r\nreturn";
+    let errors = VMInstructionParser::new("Lab.vm").parse_str(source).unwrap_err();
+    let reported : Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+    assert_eq!(reported, vec![
+        "Lab.vm:4: Label B is not defined in function Foo.bar - `goto B // nowhere`",
+        "Lab.vm:7: Label A is not defined in function Foo.baz - `if-goto A`"
+    ]);
+}
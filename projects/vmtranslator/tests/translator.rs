@@ -0,0 +1,226 @@
+use assembler::{Assembler, Instruction};
+use vmtranslator::{Compiler, VMInstructionParser};
+
+mod common;
+use common::{run, RAM_SIZE, SP};
+
+// compiles each (filename, source) in order with one compiler
+fn compile(files : &[(&str, &str)]) -> Vec<Instruction> {
+    let mut compiler = Compiler::new();
+    files.iter().flat_map(|(filename, source)| {
+        let vm_instructions = VMInstructionParser::new(filename).parse_str(source).unwrap();
+        compiler.compile(filename, vm_instructions).unwrap()
+    }).collect()
+}
+
+// runs the program from a bare stack; the top level code must end in a HALT loop
+fn execute(instructions : Vec<Instruction>) -> Vec<u16> {
+    let rom = Assembler::new().assemble(&instructions);
+    let mut ram = vec![0; RAM_SIZE];
+    ram[SP] = 256;
+    run(&rom, &mut ram);
+    ram
+}
+
+fn labels(instructions : &[Instruction]) -> Vec<String> {
+    instructions.iter().filter_map(|ins| match ins {
+        Instruction::LInstruction { symbol } => Some(symbol.clone()),
+        _ => None
+    }).collect()
+}
+
+// both functions loop on a label named LOOP, and Test.b calls Test.a twice
+const LABELS : &str = "
+push constant 3
+call Test.a 1
+pop static 0
+push constant 4
+call Test.b 1
+pop static 1
+label HALT
+goto HALT
+function Test.a 0
+push constant 0
+label LOOP
+push argument 0
+add
+push argument 0
+push constant 1
+sub
+pop argument 0
+push argument 0
+if-goto LOOP
+return
+function Test.b 0
+push constant 0
+label LOOP
+push constant 5
+add
+push argument 0
+push constant 1
+sub
+pop argument 0
+push argument 0
+if-goto LOOP
+push constant 1
+call Test.a 1
+add
+push constant 2
+call Test.a 1
+add
+return
+";
+
+#[test]
+fn labels_and_return_addresses_are_scoped_to_their_function() {
+    let instructions = compile(&[("Test.vm", LABELS)]);
+    let labels = labels(&instructions);
+    for label in ["HALT", "ret.0", "ret.1", "Test.a$LOOP", "Test.b$LOOP", "Test.b$ret.0", "Test.b$ret.1"] {
+        assert!(labels.contains(&label.to_string()), "missing label {} in {:?}", label, labels);
+    }
+}
+
+#[test]
+fn functions_sharing_a_label_name_run_correctly() {
+    let ram = execute(compile(&[("Test.vm", LABELS)]));
+    // a(3) = 3 + 2 + 1, b(4) = 4 * 5 + a(1) + a(2)
+    assert_eq!(ram[16..18], [6, 24]);
+    assert_eq!(ram[SP], 256);
+}
+
+const MAIN : &str = "
+push constant 11
+call A.set 1
+pop temp 0
+push constant 22
+call B.set 1
+pop temp 0
+call A.get 0
+pop temp 1
+call B.get 0
+pop temp 2
+label HALT
+goto HALT
+";
+
+// A.vm and B.vm differ only in their names, both keep a value in static 0
+fn class(name : &str) -> String {
+    format!("
+function {0}.set 0
+push argument 0
+pop static 0
+push constant 0
+return
+function {0}.get 0
+push static 0
+return
+", name)
+}
+
+fn text(instructions : &[Instruction]) -> Vec<String> {
+    instructions.iter().map(|ins| ins.to_string()).collect()
+}
+
+#[test]
+fn statics_are_named_after_their_file() {
+    let (a, b) = (class("A"), class("B"));
+    let instructions = text(&compile(&[("A.vm", &a), ("B.vm", &b)]));
+    assert!(instructions.contains(&"@A.0".to_string()));
+    assert!(instructions.contains(&"@B.0".to_string()));
+}
+
+#[test]
+fn statics_do_not_depend_on_file_order() {
+    let (a, b) = (class("A"), class("B"));
+    let a_first = compile(&[("A.vm", &a), ("B.vm", &b)]);
+    let b_first = compile(&[("B.vm", &b), ("A.vm", &a)]);
+    let a_alone = compile(&[("A.vm", &a)]);
+    assert_eq!(text(&a_first)[..a_alone.len()], text(&b_first)[b_first.len() - a_alone.len()..]);
+
+    for files in [[("Main.vm", MAIN), ("A.vm", &a), ("B.vm", &b)], [("Main.vm", MAIN), ("B.vm", &b), ("A.vm", &a)]] {
+        let ram = execute(compile(&files));
+        // temp 1 and temp 2
+        assert_eq!(ram[6..8], [11, 22]);
+    }
+}
+
+// several calls, returns and comparisons, so sharing their code pays off
+const ROUTINES : &str = "
+push constant 5
+push constant 7
+call Test.max 2
+pop static 0
+push constant 9
+push constant 3
+call Test.max 2
+pop static 1
+push constant 4
+push constant 4
+call Test.compare 2
+pop static 2
+push constant 2
+push constant 6
+call Test.compare 2
+pop static 3
+push constant 1
+neg
+push constant 1
+neg
+push constant 2
+sub
+call Test.compare 2
+pop static 4
+label HALT
+goto HALT
+function Test.max 0
+push argument 0
+push argument 1
+gt
+if-goto FIRST
+push argument 1
+return
+label FIRST
+push argument 0
+return
+function Test.compare 0
+push argument 0
+push argument 1
+eq
+push constant 1
+and
+push argument 0
+push argument 1
+lt
+push constant 2
+and
+add
+push argument 0
+push argument 1
+gt
+push constant 4
+and
+add
+return
+";
+
+fn compile_routines(shared : bool) -> Vec<Instruction> {
+    let mut compiler = Compiler::new();
+    compiler.set_shared_routines(shared);
+    let vm_instructions = VMInstructionParser::new("Test.vm").parse_str(ROUTINES).unwrap();
+    let mut instructions = compiler.compile("Test.vm", vm_instructions).unwrap();
+    instructions.append(&mut compiler.generate_runtime());
+    instructions
+}
+
+#[test]
+fn shared_routines_keep_behaviour_and_shrink_the_program() {
+    let inlined = compile_routines(false);
+    let shared = compile_routines(true);
+    let (inlined_size, shared_size) = (Assembler::new().assemble(&inlined).len(), Assembler::new().assemble(&shared).len());
+    let (inlined_ram, shared_ram) = (execute(inlined), execute(shared));
+    // max(5, 7), max(9, 3), then 1 for eq, 2 for lt and 4 for gt
+    assert_eq!(inlined_ram[16..21], [7, 9, 1, 2, 4]);
+    assert_eq!(inlined_ram[16..21], shared_ram[16..21]);
+    assert_eq!(inlined_ram[SP], shared_ram[SP]);
+    assert!(shared_size < inlined_size, "expected shared routines to shrink the program, {} -> {}", inlined_size, shared_size);
+}